@@ -0,0 +1,59 @@
+//! Counts the allocations made parsing a program full of `true`, `false`, `nil` and small
+//! numbers, with and without `Config::intern_literals`. Run with
+//! `cargo run --release --example literal_interning [statements]`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use lox::config::Config;
+
+/// Counts every allocation made through it, leaving the work to the system allocator
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn main() {
+    let statements: usize = std::env::args()
+        .nth(1)
+        .map(|n| {
+            n.parse()
+                .expect("The number of statements must be a number")
+        })
+        .unwrap_or(10_000);
+    let code: String = (0..statements)
+        .map(|i| format!("print {} == {} ? true : (false, nil);\n", i % 100, i % 7))
+        .collect();
+
+    for intern_literals in [false, true] {
+        let config = Config {
+            comma_operator: true,
+            intern_literals,
+            ..Config::default()
+        };
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let ast = lox::parse(&code, &config).unwrap();
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        println!(
+            "{} statements parsed with{} interning in {} allocations",
+            ast.len(),
+            if intern_literals { "" } else { "out" },
+            allocations
+        );
+    }
+}
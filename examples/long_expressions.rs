@@ -0,0 +1,54 @@
+//! Times parsing and evaluating arithmetic expressions with thousands of operators, the case
+//! `eval_operators` in the interpreter exists for, against evaluating them by recursion. Run with
+//! `cargo run --release --example long_expressions [terms]`.
+
+use std::time::{Duration, Instant};
+
+use lox::{ast::Stmt, config::Config, interpreter::Interpreter};
+
+const RUNS: u32 = 20;
+/// As the lox binary runs programs, which recursion needs for as many terms as the default
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+fn main() {
+    let terms: usize = std::env::args()
+        .nth(1)
+        .map(|terms| terms.parse().expect("The number of terms must be a number"))
+        .unwrap_or(30_000);
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(terms))
+        .expect("Error starting the benchmark thread")
+        .join()
+        .unwrap();
+}
+
+fn run(terms: usize) {
+    let code = format!("var x = {};", vec!["1 * 2 - 1"; terms].join(" + "));
+
+    let start = Instant::now();
+    let ast = lox::parse(&code, &Config::default()).unwrap();
+    println!("Parsed {} terms in {:?}", terms, start.elapsed());
+
+    let explicit_stack = time(&ast, Config::default());
+    let recursion = time(
+        &ast,
+        Config {
+            long_chain: None,
+            ..Config::default()
+        },
+    );
+    println!(
+        "Evaluated them in {:?} with an explicit stack and {:?} by recursion, on average over {} runs",
+        explicit_stack, recursion, RUNS
+    );
+}
+
+fn time(ast: &[Stmt], config: Config) -> Duration {
+    let interpreter = Interpreter::new().with_config(config);
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        interpreter.interpret(ast).unwrap();
+    }
+    start.elapsed() / RUNS
+}
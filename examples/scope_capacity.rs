@@ -0,0 +1,47 @@
+//! Times creating many small scopes, as running blocks and calls does, sized up front for the
+//! variables they declare against growing as they are declared. Run with
+//! `cargo run --release --example scope_capacity [variables]`.
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use lox::interpreter::{Environment, LoxResult};
+
+const SCOPES: usize = 1_000_000;
+
+fn main() {
+    let variables: usize = std::env::args()
+        .nth(1)
+        .map(|variables| {
+            variables
+                .parse()
+                .expect("The number of variables must be a number")
+        })
+        .unwrap_or(4);
+    let names: Vec<String> = (0..variables).map(|i| format!("v{}", i)).collect();
+    let globals = Rc::new(RefCell::new(Environment::new()));
+
+    // Each run is done twice so neither pays for warming up alone
+    time(&names, &globals, 0);
+    let growing = time(&names, &globals, 0);
+    time(&names, &globals, variables);
+    let sized = time(&names, &globals, variables);
+    println!(
+        "Created {} scopes declaring {} variables in {:?} growing them and {:?} sized up front",
+        SCOPES, variables, growing, sized
+    );
+}
+
+fn time(names: &[String], globals: &Rc<RefCell<Environment>>, capacity: usize) -> Duration {
+    let start = Instant::now();
+    for i in 0..SCOPES {
+        let mut scope = Environment::with_capacity(capacity, Some(globals.clone()));
+        for name in names {
+            scope.declare(name.clone(), Some(LoxResult::Number(i as f64)));
+        }
+    }
+    start.elapsed()
+}
@@ -1,9 +1,44 @@
+use std::rc::Rc;
+
 #[derive(Debug, PartialEq)]
 pub enum Stmt {
     Expression(Expr),
     Print(Expr),
-    Variable(String, Option<Expr>),
+    /// The span is the one of the declared name
+    Variable {
+        name: String,
+        initializer: Option<Expr>,
+        index: usize,
+        len: usize,
+    },
     Block(Vec<Stmt>),
+    If {
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+    },
+    /// The span is the one of the function name. The body is shared with the functions created
+    /// when the declaration runs, which outlive the tree.
+    /// `attributes` are the names of the `@name` written before `fun`, which the interpreter
+    /// ignores
+    Function {
+        name: String,
+        params: Vec<String>,
+        attributes: Vec<String>,
+        body: Rc<Vec<Stmt>>,
+        index: usize,
+        len: usize,
+    },
+    /// The span is the one of the `return` keyword
+    Return {
+        value: Option<Expr>,
+        index: usize,
+        len: usize,
+    },
 }
 
 #[derive(Debug, PartialEq)]
@@ -20,8 +55,10 @@ pub enum Expr {
         index: usize,
         len: usize,
     },
+    /// The value is shared with other literals of the same value when parsed with
+    /// `Config::intern_literals`
     Literal {
-        value: Literal,
+        value: Rc<Literal>,
         index: usize,
         len: usize,
     },
@@ -49,6 +86,38 @@ pub enum Expr {
         index: usize,
         len: usize,
     },
+    Logical {
+        left: Box<Expr>,
+        operator: LogicalOp,
+        right: Box<Expr>,
+        index: usize,
+        len: usize,
+    },
+    Call {
+        callee: Box<Expr>,
+        // Boxed rather than a `Vec` to keep `Expr` small, parsing nested expressions is
+        // bounded by how many of them fit on the stack
+        arguments: Box<[Expr]>,
+        index: usize,
+        len: usize,
+    },
+}
+
+impl Stmt {
+    /// Where the statement is, for statements without a span of their own the span of the
+    /// expression they start with. `None` for blocks, which are only the statements they hold.
+    pub fn span(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::Expression(e) | Self::Print(e) => Some((e.index(), e.len())),
+            Self::If { condition, .. } | Self::While { condition, .. } => {
+                Some((condition.index(), condition.len()))
+            }
+            Self::Block(_) => None,
+            Self::Variable { index, len, .. }
+            | Self::Function { index, len, .. }
+            | Self::Return { index, len, .. } => Some((*index, *len)),
+        }
+    }
 }
 
 impl Expr {
@@ -95,9 +164,24 @@ impl Expr {
                 len: _,
                 index,
             } => *index,
+            Self::Logical {
+                left: _,
+                operator: _,
+                right: _,
+                len: _,
+                index,
+            } => *index,
+            Self::Call {
+                callee: _,
+                arguments: _,
+                len: _,
+                index,
+            } => *index,
         }
     }
 
+    // The length of the span in the source, the expression isn't a collection
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         match self {
             Self::Literal {
@@ -141,6 +225,86 @@ impl Expr {
                 key: _,
                 value: _,
             } => *len,
+            Self::Logical {
+                left: _,
+                operator: _,
+                right: _,
+                len,
+                index: _,
+            } => *len,
+            Self::Call {
+                callee: _,
+                arguments: _,
+                len,
+                index: _,
+            } => *len,
+        }
+    }
+
+    /// Calls `f` with each subexpression, in the order they are evaluated
+    pub(crate) fn for_each_child(&mut self, mut f: impl FnMut(&mut Expr)) {
+        match self {
+            Self::Binary { left, right, .. } | Self::Logical { left, right, .. } => {
+                f(left);
+                f(right);
+            }
+            Self::Grouping { expr, .. } => f(expr),
+            Self::Unary { right, .. } => f(right),
+            Self::Ternary {
+                condition,
+                left,
+                right,
+                ..
+            } => {
+                f(condition);
+                f(left);
+                f(right);
+            }
+            Self::Assign { value, .. } => f(value),
+            Self::Call {
+                callee, arguments, ..
+            } => {
+                f(callee);
+                arguments.iter_mut().for_each(f);
+            }
+            Self::Literal { .. } | Self::Variable { .. } => {}
+        }
+    }
+
+    /// Moves the expression out, leaving one that owns nothing in its place
+    pub(crate) fn take(&mut self) -> Expr {
+        std::mem::replace(
+            self,
+            Self::Variable {
+                value: String::new(),
+                index: 0,
+                len: 0,
+            },
+        )
+    }
+
+    fn is_leaf(&self) -> bool {
+        matches!(self, Self::Literal { .. } | Self::Variable { .. })
+    }
+}
+
+/// Dropping the tree with the derived recursion costs a Rust frame per node, so long operator
+/// chains would overflow the stack. Subexpressions are instead moved to a list and dropped from
+/// there once they no longer own others.
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.for_each_child(|child| {
+            if !child.is_leaf() {
+                pending.push(child.take())
+            }
+        });
+        while let Some(mut expr) = pending.pop() {
+            expr.for_each_child(|child| {
+                if !child.is_leaf() {
+                    pending.push(child.take())
+                }
+            });
         }
     }
 }
@@ -160,6 +324,13 @@ pub enum BinOp {
     Comma,
 }
 
+/// Operators that may skip evaluating their right operand
+#[derive(Debug, PartialEq)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Literal {
     Str(String),
@@ -173,4 +344,421 @@ pub enum Literal {
 pub enum UnaryOp {
     Negate,
     LogicNegate,
+    /// Postfix `!`, fails on `nil` and yields the operand otherwise
+    AssertNotNil,
+}
+
+/// Fields of a variant as (name, type) pairs. Tuple variants use their position as the name.
+type Fields = &'static [(&'static str, &'static str)];
+
+/// Hand-written description of every AST type. The test below fails to compile when a variant is
+/// added without listing it here.
+const SCHEMA: &[(&str, &[(&str, Fields)])] = &[
+    (
+        "Stmt",
+        &[
+            ("Expression", &[("0", "Expr")]),
+            ("Print", &[("0", "Expr")]),
+            (
+                "Variable",
+                &[
+                    ("name", "String"),
+                    ("initializer", "Option<Expr>"),
+                    ("index", "usize"),
+                    ("len", "usize"),
+                ],
+            ),
+            ("Block", &[("0", "Vec<Stmt>")]),
+            (
+                "If",
+                &[
+                    ("condition", "Expr"),
+                    ("then_branch", "Stmt"),
+                    ("else_branch", "Option<Stmt>"),
+                ],
+            ),
+            ("While", &[("condition", "Expr"), ("body", "Stmt")]),
+            (
+                "Function",
+                &[
+                    ("name", "String"),
+                    ("params", "Vec<String>"),
+                    ("attributes", "Vec<String>"),
+                    ("body", "Vec<Stmt>"),
+                    ("index", "usize"),
+                    ("len", "usize"),
+                ],
+            ),
+            (
+                "Return",
+                &[
+                    ("value", "Option<Expr>"),
+                    ("index", "usize"),
+                    ("len", "usize"),
+                ],
+            ),
+        ],
+    ),
+    (
+        "Expr",
+        &[
+            (
+                "Binary",
+                &[
+                    ("left", "Expr"),
+                    ("operator", "BinOp"),
+                    ("right", "Expr"),
+                    ("index", "usize"),
+                    ("len", "usize"),
+                ],
+            ),
+            (
+                "Grouping",
+                &[("expr", "Expr"), ("index", "usize"), ("len", "usize")],
+            ),
+            (
+                "Literal",
+                &[("value", "Literal"), ("index", "usize"), ("len", "usize")],
+            ),
+            (
+                "Unary",
+                &[
+                    ("operator", "UnaryOp"),
+                    ("right", "Expr"),
+                    ("index", "usize"),
+                    ("len", "usize"),
+                ],
+            ),
+            (
+                "Ternary",
+                &[
+                    ("condition", "Expr"),
+                    ("left", "Expr"),
+                    ("right", "Expr"),
+                    ("index", "usize"),
+                    ("len", "usize"),
+                ],
+            ),
+            (
+                "Variable",
+                &[("value", "String"), ("index", "usize"), ("len", "usize")],
+            ),
+            (
+                "Assign",
+                &[
+                    ("key", "String"),
+                    ("value", "Expr"),
+                    ("index", "usize"),
+                    ("len", "usize"),
+                ],
+            ),
+            (
+                "Logical",
+                &[
+                    ("left", "Expr"),
+                    ("operator", "LogicalOp"),
+                    ("right", "Expr"),
+                    ("index", "usize"),
+                    ("len", "usize"),
+                ],
+            ),
+            (
+                "Call",
+                &[
+                    ("callee", "Expr"),
+                    ("arguments", "Box<[Expr]>"),
+                    ("index", "usize"),
+                    ("len", "usize"),
+                ],
+            ),
+        ],
+    ),
+    (
+        "BinOp",
+        &[
+            ("Sum", &[]),
+            ("Substraction", &[]),
+            ("Product", &[]),
+            ("Division", &[]),
+            ("Equals", &[]),
+            ("NotEquals", &[]),
+            ("GreaterThan", &[]),
+            ("GreaterThanEquals", &[]),
+            ("LessThan", &[]),
+            ("LessThanEquals", &[]),
+            ("Comma", &[]),
+        ],
+    ),
+    ("LogicalOp", &[("And", &[]), ("Or", &[])]),
+    (
+        "Literal",
+        &[
+            ("Str", &[("0", "String")]),
+            ("Number", &[("0", "f64")]),
+            ("True", &[]),
+            ("False", &[]),
+            ("Nil", &[]),
+        ],
+    ),
+    (
+        "UnaryOp",
+        &[("Negate", &[]), ("LogicNegate", &[]), ("AssertNotNil", &[])],
+    ),
+];
+
+/// JSON description of the AST types, mapping each type to its variants and each variant to its
+/// fields and their types
+pub fn json_schema() -> String {
+    let types: Vec<String> = SCHEMA
+        .iter()
+        .map(|(name, variants)| {
+            let variants: Vec<String> = variants
+                .iter()
+                .map(|(variant, fields)| {
+                    let fields: Vec<String> = fields
+                        .iter()
+                        .map(|(field, kind)| format!("\"{}\": \"{}\"", field, kind))
+                        .collect();
+                    format!("    \"{}\": {{{}}}", variant, fields.join(", "))
+                })
+                .collect();
+            format!("  \"{}\": {{\n{}\n  }}", name, variants.join(",\n"))
+        })
+        .collect();
+    format!("{{\n{}\n}}", types.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{json_schema, BinOp, Expr, Literal, LogicalOp, Stmt, UnaryOp, SCHEMA};
+
+    // Exhaustive matches, so adding a variant breaks the build until the schema is updated
+    fn stmt_variant(stmt: &Stmt) -> &'static str {
+        match stmt {
+            Stmt::Expression(_) => "Expression",
+            Stmt::Print(_) => "Print",
+            Stmt::Variable { .. } => "Variable",
+            Stmt::Block(_) => "Block",
+            Stmt::If { .. } => "If",
+            Stmt::While { .. } => "While",
+            Stmt::Function { .. } => "Function",
+            Stmt::Return { .. } => "Return",
+        }
+    }
+
+    fn expr_variant(expr: &Expr) -> &'static str {
+        match expr {
+            Expr::Binary { .. } => "Binary",
+            Expr::Grouping { .. } => "Grouping",
+            Expr::Literal { .. } => "Literal",
+            Expr::Unary { .. } => "Unary",
+            Expr::Ternary { .. } => "Ternary",
+            Expr::Variable { .. } => "Variable",
+            Expr::Assign { .. } => "Assign",
+            Expr::Logical { .. } => "Logical",
+            Expr::Call { .. } => "Call",
+        }
+    }
+
+    fn bin_op_variant(operator: &BinOp) -> &'static str {
+        match operator {
+            BinOp::Sum => "Sum",
+            BinOp::Substraction => "Substraction",
+            BinOp::Product => "Product",
+            BinOp::Division => "Division",
+            BinOp::Equals => "Equals",
+            BinOp::NotEquals => "NotEquals",
+            BinOp::GreaterThan => "GreaterThan",
+            BinOp::GreaterThanEquals => "GreaterThanEquals",
+            BinOp::LessThan => "LessThan",
+            BinOp::LessThanEquals => "LessThanEquals",
+            BinOp::Comma => "Comma",
+        }
+    }
+
+    fn logical_op_variant(operator: &LogicalOp) -> &'static str {
+        match operator {
+            LogicalOp::And => "And",
+            LogicalOp::Or => "Or",
+        }
+    }
+
+    fn literal_variant(literal: &Literal) -> &'static str {
+        match literal {
+            Literal::Str(_) => "Str",
+            Literal::Number(_) => "Number",
+            Literal::True => "True",
+            Literal::False => "False",
+            Literal::Nil => "Nil",
+        }
+    }
+
+    fn unary_op_variant(operator: &UnaryOp) -> &'static str {
+        match operator {
+            UnaryOp::Negate => "Negate",
+            UnaryOp::LogicNegate => "LogicNegate",
+            UnaryOp::AssertNotNil => "AssertNotNil",
+        }
+    }
+
+    fn schema_variants(name: &str) -> Vec<&'static str> {
+        let (_, variants) = SCHEMA.iter().find(|(n, _)| *n == name).unwrap();
+        variants.iter().map(|(variant, _)| *variant).collect()
+    }
+
+    #[test]
+    fn schema_lists_every_variant() {
+        let expr = || Expr::Literal {
+            value: Literal::Nil.into(),
+            index: 0,
+            len: 3,
+        };
+        let stmts = [
+            Stmt::Expression(expr()),
+            Stmt::Print(expr()),
+            Stmt::Variable {
+                name: String::from("a"),
+                initializer: None,
+                index: 0,
+                len: 0,
+            },
+            Stmt::Block(vec![]),
+            Stmt::If {
+                condition: expr(),
+                then_branch: Stmt::Block(vec![]).into(),
+                else_branch: None,
+            },
+            Stmt::While {
+                condition: expr(),
+                body: Stmt::Block(vec![]).into(),
+            },
+            Stmt::Function {
+                name: String::from("f"),
+                params: vec![],
+                attributes: vec![],
+                body: Rc::new(vec![]),
+                index: 0,
+                len: 0,
+            },
+            Stmt::Return {
+                value: None,
+                index: 0,
+                len: 0,
+            },
+        ];
+        let exprs = [
+            Expr::Binary {
+                left: expr().into(),
+                operator: BinOp::Sum,
+                right: expr().into(),
+                index: 0,
+                len: 0,
+            },
+            Expr::Grouping {
+                expr: expr().into(),
+                index: 0,
+                len: 0,
+            },
+            expr(),
+            Expr::Unary {
+                operator: UnaryOp::Negate,
+                right: expr().into(),
+                index: 0,
+                len: 0,
+            },
+            Expr::Ternary {
+                condition: expr().into(),
+                left: expr().into(),
+                right: expr().into(),
+                index: 0,
+                len: 0,
+            },
+            Expr::Variable {
+                value: String::from("a"),
+                index: 0,
+                len: 0,
+            },
+            Expr::Assign {
+                key: String::from("a"),
+                value: expr().into(),
+                index: 0,
+                len: 0,
+            },
+            Expr::Logical {
+                left: expr().into(),
+                operator: LogicalOp::Or,
+                right: expr().into(),
+                index: 0,
+                len: 0,
+            },
+            Expr::Call {
+                callee: expr().into(),
+                arguments: Box::new([]),
+                index: 0,
+                len: 0,
+            },
+        ];
+        let bin_ops = [
+            BinOp::Sum,
+            BinOp::Substraction,
+            BinOp::Product,
+            BinOp::Division,
+            BinOp::Equals,
+            BinOp::NotEquals,
+            BinOp::GreaterThan,
+            BinOp::GreaterThanEquals,
+            BinOp::LessThan,
+            BinOp::LessThanEquals,
+            BinOp::Comma,
+        ];
+        let logical_ops = [LogicalOp::And, LogicalOp::Or];
+        let literals = [
+            Literal::Str(String::new()),
+            Literal::Number(0.0),
+            Literal::True,
+            Literal::False,
+            Literal::Nil,
+        ];
+        let unary_ops = [UnaryOp::Negate, UnaryOp::LogicNegate, UnaryOp::AssertNotNil];
+
+        assert_eq!(
+            schema_variants("Stmt"),
+            stmts.iter().map(stmt_variant).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            schema_variants("Expr"),
+            exprs.iter().map(expr_variant).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            schema_variants("BinOp"),
+            bin_ops.iter().map(bin_op_variant).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            schema_variants("LogicalOp"),
+            logical_ops
+                .iter()
+                .map(logical_op_variant)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            schema_variants("Literal"),
+            literals.iter().map(literal_variant).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            schema_variants("UnaryOp"),
+            unary_ops.iter().map(unary_op_variant).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn json_schema_output() {
+        let schema = json_schema();
+        assert!(schema.starts_with("{\n  \"Stmt\": {\n    \"Expression\": {\"0\": \"Expr\"},\n"));
+        assert!(schema.contains(
+            "    \"Assign\": {\"key\": \"String\", \"value\": \"Expr\", \"index\": \"usize\", \"len\": \"usize\"}"
+        ));
+        assert!(schema.ends_with("    \"AssertNotNil\": {}\n  }\n}"));
+    }
 }
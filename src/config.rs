@@ -0,0 +1,44 @@
+/// Settings shared by every stage a program goes through, from parsing to running it. The
+/// default is the language as documented.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Treat `,` as a binary operator that evaluates both sides and yields the right one
+    pub comma_operator: bool,
+    /// How deep code may nest through blocks, parentheses, prefix operators or ternary branches
+    pub max_depth: usize,
+    /// How many calls to Lox functions may be running at once, one inside another
+    pub max_call_depth: usize,
+    /// Let statements end without `;` when nothing else continues them
+    pub lenient_semicolons: bool,
+    /// Refuse to run programs that have warnings
+    pub deny_warnings: bool,
+    /// Write the value of top level expression statements to the output, as a REPL does
+    pub echo_expression_values: bool,
+    /// Share one node between every `true`, `false`, `nil` and whole number literal from 0 to 255
+    /// of the same value instead of allocating one for each
+    pub intern_literals: bool,
+    /// Turn strings into the numbers they spell when used with a number or in arithmetic other
+    /// than concatenation, so `"3" + 4` is `7`
+    pub coerce: bool,
+    /// Binary operators whose left operands nest this deep, as in `1 + 2 + ... + n`, are evaluated
+    /// with an explicit stack instead of recursion, which would run out of stack on long chains.
+    /// Below it recursion is faster, since the explicit stacks cost two allocations per
+    /// expression. `None` always recurses.
+    pub long_chain: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            comma_operator: false,
+            max_depth: 100,
+            max_call_depth: 100,
+            lenient_semicolons: false,
+            deny_warnings: false,
+            echo_expression_values: false,
+            intern_literals: false,
+            coerce: false,
+            long_chain: Some(64),
+        }
+    }
+}
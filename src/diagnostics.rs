@@ -0,0 +1,354 @@
+//! Rendering of errors and warnings against the source they point into
+
+use crate::lexer;
+
+/// Longer descriptions of each error and warning code, with an example and how to fix it
+const EXPLANATIONS: [(&str, &str); 23] = [
+    (
+        "E001",
+        "A statement is missing the `;` that ends it.
+
+    var a = 1
+    print a;
+
+Add the semicolon after the statement:
+
+    var a = 1;",
+    ),
+    (
+        "E002",
+        "A token other than the one the grammar expects at that point was found, like a missing
+parenthesis or name.
+
+    if a > 1 print a;
+
+Write the missing token, here the parentheses around the condition:
+
+    if (a > 1) print a;",
+    ),
+    (
+        "E003",
+        "An expression was expected but the code ends or continues with something that can't start
+one.
+
+    var a = ;
+    print;
+
+Write the missing expression:
+
+    var a = 1;
+    print a;",
+    ),
+    (
+        "E004",
+        "A block was opened with `{` but the end of the file was reached before its `}`.
+
+    while (true) {
+        print 1;
+
+Close the block:
+
+    while (true) {
+        print 1;
+    }",
+    ),
+    (
+        "E005",
+        "Only variables can be assigned, the left side of `=` is something else.
+
+    a + b = 1;
+    (a) = 1;
+
+Assign to the variable itself:
+
+    a = 1;",
+    ),
+    (
+        "E006",
+        "Functions can't declare more than 255 parameters and calls can't pass more than 255
+arguments.
+
+Group related values some other way, or split the function in smaller ones.",
+    ),
+    (
+        "E007",
+        "A `return` statement was found outside of any function.
+
+    return 1;
+
+Only function bodies can return, at the top level the program simply ends after its last
+statement.",
+    ),
+    (
+        "E008",
+        "A number literal can't be read as a number.
+
+    var a = 0x;
+    var b = 0b102;
+    var c = 1.5n;
+
+Hexadecimal numbers need digits after `0x`, binary ones only take up to 64 zeros and ones after
+`0b`, and the `n` suffix only goes after numbers written without a fraction or exponent:
+
+    var a = 0x1f;
+    var b = 0b101;
+    var c = 15n;",
+    ),
+    (
+        "E009",
+        "The code nests too deep, through parentheses, blocks, prefix operators or ternary
+branches, for the parser to follow.
+
+Move some of the nested parts to variables or functions of their own.",
+    ),
+    (
+        "E010",
+        "A string contains a `\\` followed by something that doesn't make an escape sequence.
+
+    print \"C:\\quarter\\data\";
+    print \"\\u{110000}\";
+
+The known escape sequences are `\\n`, `\\t`, `\\r`, `\\0`, `\\\"`, `\\\\` and `\\u{...}`, which takes
+up to six hex digits naming a Unicode character. Write a backslash as `\\\\`:
+
+    print \"C:\\\\quarter\\\\data\";
+    print \"\\u{1F600}\";",
+    ),
+    (
+        "E011",
+        "The code has a character that isn't part of the language, outside of strings and
+comments.
+
+    var total = 1 # 2;
+
+Remove it, or quote it if it was meant as text. `@` is only accepted where it starts an
+attribute of a function declaration, like `@memoize fun f() {}`.",
+    ),
+    (
+        "E101",
+        "An operator was used on values of types it doesn't support.
+
+    print 1 - \"a\";
+    print true < false;
+
+Arithmetic needs numbers, only numbers and strings can be ordered, and `+` joins strings with
+any other value. Convert the values first, or check what the variables hold.",
+    ),
+    (
+        "E102",
+        "A variable was read or assigned before being declared.
+
+    print count;
+    count = 1;
+
+Declare it with `var` first:
+
+    var count = 1;
+    print count;",
+    ),
+    (
+        "E103",
+        "A number was divided by zero.
+
+    var average = total / count;
+
+Check the divisor before dividing:
+
+    if (count != 0) average = total / count;",
+    ),
+    (
+        "E104",
+        "The value before a postfix `!` was `nil`, which `!` asserts it isn't.
+
+    var a;
+    print a!;
+
+Give the value a value before asserting it, or check it for `nil` with `==` instead.",
+    ),
+    (
+        "E105",
+        "Something that isn't a function was called.
+
+    var a = 1;
+    a();
+
+Only functions can be called, check that the name refers to the function meant.",
+    ),
+    (
+        "E106",
+        "A function was called with a different number of arguments than it declares parameters.
+
+    fun add(a, b) { return a + b; }
+    add(1);
+
+Pass one argument per parameter:
+
+    add(1, 2);",
+    ),
+    (
+        "E107",
+        "A native function, one provided by the interpreter like `clock`, failed. The message tells
+what went wrong.",
+    ),
+    (
+        "E108",
+        "Functions called one inside another nested deeper than allowed, most often because a
+recursive function never reaches the case that stops it.
+
+    fun count(n) { return count(n + 1); }
+    count(0);
+
+Make sure every recursion ends:
+
+    fun count(n) { if (n == 10) return n; return count(n + 1); }",
+    ),
+    (
+        "E109",
+        "The program was stopped while running a loop, by Ctrl-C in the REPL. The error points at
+the condition of the loop that was running.",
+    ),
+    (
+        "W001",
+        "A local variable is declared but its value is never read.
+
+    {
+        var unused = 1;
+        print \"done\";
+    }
+
+Assigning to a variable doesn't count as reading it. Remove the declaration, or use the
+variable if it was meant to be. Globals aren't checked since later files may read them.",
+    ),
+    (
+        "W002",
+        "An expression statement computes a value that is thrown away without any side effect.
+
+    var a = 1;
+    a + 1;
+
+The statement does nothing, most likely an assignment or a call was meant:
+
+    a = a + 1;",
+    ),
+    (
+        "W003",
+        "A statement comes after a `return` in the same block, so it never runs.
+
+    fun f() {
+        return 1;
+        print \"never\";
+    }
+
+Remove the statements after the `return`, or move the `return` after them if they were meant
+to run.",
+    ),
+];
+
+/// Explanation of an error or warning `code` such as `W001`, `None` for unknown codes
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(c, _)| c.eq_ignore_ascii_case(code))
+        .map(|(_, explanation)| *explanation)
+}
+
+/// Heading of a diagnostic, like `Error[E101]: message at 2:7`. `kind` is `Error` or `Warning`
+/// and the `index` it points at is located by line and column within `code`, which starts at line
+/// `first_line` of a larger source.
+pub fn locate(
+    kind: &str,
+    code_id: &str,
+    message: &str,
+    index: usize,
+    code: &str,
+    first_line: usize,
+) -> String {
+    let (line, column) = lexer::line_column(code, index);
+    format!(
+        "{}[{}]: {} at {}:{}",
+        kind,
+        code_id,
+        message,
+        first_line + line - 1,
+        column
+    )
+}
+
+/// Quotes the line of `code` holding the byte `index`, rustc style, with the line number in a
+/// gutter and carets under the `len` bytes starting at `index`. Spans running past the end of the
+/// line are underlined up to it, and empty spans still get a caret.
+pub fn snippet(code: &str, index: usize, len: usize) -> String {
+    snippet_at(code, 1, index, len)
+}
+
+/// Like `snippet` for `code` starting at line `first_line` of a larger source
+pub fn snippet_at(code: &str, first_line: usize, index: usize, len: usize) -> String {
+    let index = index.min(code.len());
+    let line = first_line + lexer::line_column(code, index).0 - 1;
+    let start = code[..index].rfind('\n').map_or(0, |i| i + 1);
+    let end = code[index..].find('\n').map_or(code.len(), |i| index + i);
+    let text = code[start..end].trim_end_matches('\r');
+
+    // Tabs are kept so the carets line up however wide the terminal shows them
+    let padding: String = code[start..index]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let underlined = &code[index..(index + len).min(start + text.len()).max(index)];
+    let carets = "^".repeat(underlined.chars().count().max(1));
+
+    let gutter = " ".repeat(line.to_string().len());
+    format!(
+        "{} |\n{} | {}\n{} | {}{}",
+        gutter, line, text, gutter, padding, carets
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{explain, locate, snippet};
+
+    #[test]
+    fn explain_codes() {
+        assert!(explain("W001").unwrap().contains("never read"));
+        assert_eq!(explain("w002"), explain("W002"));
+        assert!(explain("E103").unwrap().contains("divided by zero"));
+        assert_eq!(explain("W999"), None);
+    }
+
+    #[test]
+    fn locate_in_chunks() {
+        let code = "var a;\n  a + nil;";
+        assert_eq!(
+            locate("Error", "E101", "Oops", 9, code, 1),
+            "Error[E101]: Oops at 2:3"
+        );
+        assert_eq!(
+            locate("Warning", "W001", "Hmm", 9, code, 40),
+            "Warning[W001]: Hmm at 41:3"
+        );
+    }
+
+    #[test]
+    fn underline_spans() {
+        let code = "var a = 1;\nprint a + nil;\n";
+        assert_eq!(
+            snippet(code, 17, 7),
+            "  |\n2 | print a + nil;\n  |       ^^^^^^^"
+        );
+        // Missing tokens point at the end of the input
+        assert_eq!(snippet(code, code.len(), 0), "  |\n3 | \n  | ^");
+        assert_eq!(
+            snippet("\tx = \"été\" + 1;", 5, 7),
+            "  |\n1 | \tx = \"été\" + 1;\n  | \t    ^^^^^"
+        );
+    }
+
+    #[test]
+    fn multiline_spans_underline_the_first_line() {
+        let code = "print \"a\r\nb\";";
+        assert_eq!(snippet(code, 6, 6), "  |\n1 | print \"a\n  |       ^^");
+        let code = format!("{}{{\n}}", "\n".repeat(9));
+        assert_eq!(snippet(&code, 9, 3), "   |\n10 | {\n   | ^");
+    }
+}
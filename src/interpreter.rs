@@ -1,6 +1,25 @@
-use std::{cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    convert::{TryFrom, TryInto},
+    fmt::{Debug, Display},
+    io::{self, stderr, stdin, stdout, BufRead, BufReader, Write},
+    rc::Rc,
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc,
+    },
+};
 
-use crate::ast::{BinOp, Expr, Literal, Stmt};
+use crate::{
+    ast::{BinOp, Expr, Literal, LogicalOp, Stmt, UnaryOp},
+    config::Config,
+    diagnostics, natives,
+};
+
+/// Variables of a scope at some point in time, `None` for the declared but uninitialized ones
+pub type Snapshot = HashMap<String, Option<LoxResult>>;
 
 pub struct Environment {
     scope: HashMap<String, Option<LoxResult>>,
@@ -8,17 +27,18 @@ pub struct Environment {
 }
 
 impl Environment {
+    /// Global scope with the native functions already declared
     pub fn new() -> Environment {
-        Environment {
-            scope: HashMap::new(),
-            parent: None,
-        }
+        let mut environment = Environment::with_capacity(0, None);
+        natives::install(&mut environment);
+        environment
     }
 
-    pub fn with_parent(env: Rc<RefCell<Environment>>) -> Environment {
+    /// Pre-sizes the scope so declaring up to `capacity` variables doesn't rehash
+    pub fn with_capacity(capacity: usize, parent: Option<Rc<RefCell<Environment>>>) -> Environment {
         Environment {
-            scope: HashMap::new(),
-            parent: Some(env),
+            scope: HashMap::with_capacity(capacity),
+            parent,
         }
     }
 
@@ -26,19 +46,42 @@ impl Environment {
         if let Some(parent) = &self.parent {
             self.scope
                 .get(key)
-                .map(|c| c.clone())
-                .or_else(|| parent.borrow().get(key).map(|c| c.clone()))
+                .cloned()
+                .or_else(|| parent.borrow().get(key))
             // .map(|c| c.clone())
         } else {
-            self.scope.get(key).map(|e| e.clone())
+            self.scope.get(key).cloned()
+        }
+    }
+
+    /// Number of scopes between this one and the one declaring `key`, 0 being this scope
+    pub fn locate(&self, key: &str) -> Option<usize> {
+        if self.scope.contains_key(key) {
+            Some(0)
+        } else {
+            let parent = self.parent.as_ref()?;
+            parent.borrow().locate(key).map(|depth| depth + 1)
         }
     }
 
+    /// Copy of the variables declared in this scope, without the ones of its parents
+    pub fn snapshot(&self) -> Snapshot {
+        self.scope.clone()
+    }
+
     pub fn declare(&mut self, key: String, value: Option<LoxResult>) {
         self.scope.insert(key, value);
     }
 
+    /// Declares every entry with its value, growing the scope once for all of them
+    pub fn define_many(&mut self, entries: Vec<(String, LoxResult)>) {
+        self.scope.reserve(entries.len());
+        self.scope
+            .extend(entries.into_iter().map(|(key, value)| (key, Some(value))));
+    }
+
     /// Returns an error if the variable was not declared before
+    #[allow(clippy::result_unit_err)] // Callers know the variable and report it with its span
     pub fn set(&mut self, key: &str, value: LoxResult) -> Result<(), ()> {
         if self.scope.contains_key(key) {
             self.scope.insert(key.into(), Some(value));
@@ -52,114 +95,913 @@ impl Environment {
     }
 }
 
+impl Default for Environment {
+    fn default() -> Self {
+        Environment::new()
+    }
+}
+
+/// Files as the `read_file` and `write_file` natives see them, the real ones unless replaced
+/// through `Interpreter::with_file_system`
+pub trait FileSystem {
+    fn read(&self, path: &str) -> io::Result<String>;
+    fn write(&self, path: &str, contents: &str) -> io::Result<()>;
+}
+
+/// Owns the global scope and everything a program can observe from the outside world, like where
+/// `print` writes to. Built with `Interpreter::new` and configured through the `with_*` methods.
+pub struct Interpreter {
+    globals: Rc<RefCell<Environment>>,
+    /// Where the `read_line` native reads from
+    input: RefCell<Box<dyn BufRead>>,
+    output: RefCell<Box<dyn Write>>,
+    error_output: RefCell<Box<dyn Write>>,
+    /// Seconds since the Unix epoch, what the `clock` native returns
+    clock: Box<dyn Fn() -> f64>,
+    file_system: Box<dyn FileSystem>,
+    /// State of the xorshift generator behind the `random` native, never 0
+    random: Cell<u64>,
+    config: Config,
+    /// Calls to Lox functions running, checked against `Config::max_call_depth`
+    calls: Cell<usize>,
+    /// Set from elsewhere to stop the running program, see `with_interrupt`
+    interrupt: Arc<AtomicBool>,
+    /// Spans of the statements that ran, when asked for with `with_coverage`
+    covered: Option<RefCell<HashSet<(usize, usize)>>>,
+}
+
+/// How much of a program ran, from `Interpreter::coverage`
+#[derive(Debug, PartialEq)]
+pub struct Coverage {
+    /// Count of statements in the program, blocks aside
+    pub statements: usize,
+    /// Spans of the statements that never ran, in the order they are written
+    pub uncovered: Vec<(usize, usize)>,
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter {
+            globals: Rc::new(RefCell::new(Environment::new())),
+            input: RefCell::new(Box::new(BufReader::new(stdin()))),
+            output: RefCell::new(Box::new(stdout())),
+            error_output: RefCell::new(Box::new(stderr())),
+            clock: Box::new(natives::system_clock),
+            file_system: Box::new(natives::SystemFileSystem),
+            random: Cell::new(natives::random_state(natives::system_clock().to_bits())),
+            config: Config::default(),
+            calls: Cell::new(0),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            covered: None,
+        }
+    }
+
+    /// Source of the lines `read_line` returns
+    pub fn with_input(mut self, input: impl BufRead + 'static) -> Interpreter {
+        self.input = RefCell::new(Box::new(input));
+        self
+    }
+
+    /// Sink for the output of `print` statements
+    pub fn with_output(mut self, output: impl Write + 'static) -> Interpreter {
+        self.output = RefCell::new(Box::new(output));
+        self
+    }
+
+    /// Sink for errors reported through `Interpreter::report`
+    pub fn with_error_output(mut self, error_output: impl Write + 'static) -> Interpreter {
+        self.error_output = RefCell::new(Box::new(error_output));
+        self
+    }
+
+    /// Source of the time `clock` returns, in seconds since the Unix epoch, instead of the system
+    /// clock
+    pub fn with_clock(mut self, clock: impl Fn() -> f64 + 'static) -> Interpreter {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Files programs read and write instead of the ones on disk, to sandbox them or stub them
+    pub fn with_file_system(mut self, file_system: impl FileSystem + 'static) -> Interpreter {
+        self.file_system = Box::new(file_system);
+        self
+    }
+
+    /// Seed of the numbers `random` returns, the same sequence for the same seed. Programs can
+    /// change it with the `seed` native. Seeded from the system clock otherwise.
+    pub fn with_seed(self, seed: u64) -> Interpreter {
+        self.seed(seed);
+        self
+    }
+
+    /// Restarts the sequence of `random` numbers from `seed`
+    pub fn seed(&self, seed: u64) {
+        self.random.set(natives::random_state(seed));
+    }
+
+    /// Next number of the sequence, in `[0, 1)`
+    pub fn random(&self) -> f64 {
+        let state = natives::xorshift(self.random.get());
+        self.random.set(state);
+        // The top 53 bits, as many as an f64 holds exactly
+        (state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Runs programs in `globals` instead of a new global scope
+    pub fn with_globals(mut self, globals: Rc<RefCell<Environment>>) -> Interpreter {
+        self.globals = globals;
+        self
+    }
+
+    /// Flag stopping the running program with a runtime error at its next loop iteration once
+    /// set, as Ctrl-C does in the REPL. The flag is cleared when the program stops.
+    pub fn with_interrupt(mut self, interrupt: Arc<AtomicBool>) -> Interpreter {
+        self.interrupt = interrupt;
+        self
+    }
+
+    /// Records which statements run, for `coverage` to tell which didn't
+    pub fn with_coverage(mut self) -> Interpreter {
+        self.covered = Some(RefCell::new(HashSet::new()));
+        self
+    }
+
+    /// Which statements of `stmts` ran since the last call, `None` unless built `with_coverage`.
+    /// Statements are told apart by their span, so `stmts` has to be the program that ran.
+    pub fn coverage(&self, stmts: &[Stmt]) -> Option<Coverage> {
+        fn walk(stmts: &[Stmt], covered: &HashSet<(usize, usize)>, coverage: &mut Coverage) {
+            for stmt in stmts {
+                if let Some(span) = stmt.span() {
+                    coverage.statements += 1;
+                    if !covered.contains(&span) {
+                        coverage.uncovered.push(span);
+                    }
+                }
+                match stmt {
+                    Stmt::Block(stmts) => walk(stmts, covered, coverage),
+                    Stmt::Function { body, .. } => walk(body, covered, coverage),
+                    Stmt::If {
+                        then_branch,
+                        else_branch,
+                        ..
+                    } => {
+                        walk(std::slice::from_ref(then_branch), covered, coverage);
+                        if let Some(else_branch) = else_branch {
+                            walk(std::slice::from_ref(else_branch), covered, coverage);
+                        }
+                    }
+                    Stmt::While { body, .. } => walk(std::slice::from_ref(body), covered, coverage),
+                    _ => {}
+                }
+            }
+        }
+        let covered = self.covered.as_ref()?.replace(HashSet::new());
+        let mut coverage = Coverage {
+            statements: 0,
+            uncovered: Vec::new(),
+        };
+        walk(stmts, &covered, &mut coverage);
+        Some(coverage)
+    }
+
+    /// Settings the program runs with, the same ones it was parsed with
+    pub fn with_config(mut self, config: Config) -> Interpreter {
+        self.config = config;
+        self
+    }
+
+    /// Runs the statements in the global scope, stopping at the first runtime error
+    pub fn interpret(&self, stmts: &[Stmt]) -> Result<(), LoxRuntimeError> {
+        self.interpret_cells(stmts).map(|_| ())
+    }
+
+    /// Runs the statements like `interpret` and returns the value of each one of them, in order.
+    /// Statements that aren't expressions evaluate to `nil`.
+    pub fn interpret_cells(&self, stmts: &[Stmt]) -> Result<Vec<LoxResult>, LoxRuntimeError> {
+        let mut values = Vec::with_capacity(stmts.len());
+        for stmt in stmts {
+            let value = match stmt.eval(self, self.globals.clone()) {
+                Ok(value) => value,
+                Err(Unwind::Error(e)) => return Err(e),
+                // The parser rejects `return` outside of functions, trees built some other way
+                // end the program there
+                Err(Unwind::Return(_)) => break,
+            };
+            if self.config.echo_expression_values {
+                if let Stmt::Expression(_) = stmt {
+                    writeln!(self.output.borrow_mut(), "{}", value.repr())
+                        .expect("Error writing output");
+                }
+            }
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+    pub fn globals_snapshot(&self) -> Snapshot {
+        self.globals.borrow().snapshot()
+    }
+
+    /// Evaluates a single expression in the global scope
+    pub fn evaluate(&self, expr: &Expr) -> Result<LoxResult, LoxRuntimeError> {
+        expr.eval(self, self.globals.clone())
+    }
+
+    /// Writes a value to the output the way `print` shows it
+    pub fn print(&self, value: &LoxResult) {
+        writeln!(self.output.borrow_mut(), "{}", value).expect("Error writing output");
+    }
+
+    /// Writes a value to the output like `print` but without ending the line. The output is
+    /// flushed, since sinks buffering lines would hold it back.
+    pub fn write(&self, value: &LoxResult) -> io::Result<()> {
+        let mut output = self.output.borrow_mut();
+        write!(output, "{}", value)?;
+        output.flush()
+    }
+
+    /// Next line of the input without its line ending, `None` at the end of the input. The
+    /// output is flushed first so prompts written before show up before waiting for the answer.
+    pub fn read_line(&self) -> io::Result<Option<String>> {
+        self.output.borrow_mut().flush()?;
+        let mut line = String::new();
+        if self.input.borrow_mut().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let end = line.trim_end_matches(&['\n', '\r'][..]).len();
+        line.truncate(end);
+        Ok(Some(line))
+    }
+
+    /// Current time in seconds since the Unix epoch, from the clock given to `with_clock`
+    pub fn now(&self) -> f64 {
+        (self.clock)()
+    }
+
+    /// Files as given to `with_file_system`
+    pub fn file_system(&self) -> &dyn FileSystem {
+        self.file_system.as_ref()
+    }
+
+    /// Fails if the program was interrupted since it last checked, blaming `expr`
+    fn check_interrupt(&self, expr: &Expr) -> Result<(), LoxRuntimeError> {
+        if self.interrupt.swap(false, atomic::Ordering::SeqCst) {
+            return Err(LoxRuntimeError {
+                code: "E109",
+                message: String::from("Interrupted"),
+                index: expr.index(),
+                len: expr.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Writes errors and warnings to the error output
+    pub fn report(&self, error: &dyn Display) {
+        writeln!(self.error_output.borrow_mut(), "{}", error).expect("Error writing error output");
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        release(&self.globals);
+    }
+}
+
 pub trait Interpretable {
-    fn eval(&self, environment: Rc<RefCell<Environment>>) -> Result<LoxResult, LoxRuntimeError>;
+    /// Why the evaluation may stop before producing a value
+    type Error;
+
+    fn eval(
+        &self,
+        interpreter: &Interpreter,
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<LoxResult, Self::Error>;
+}
+
+/// Stops running statements, unwinding until something handles it
+#[derive(Debug)]
+pub enum Unwind {
+    Error(LoxRuntimeError),
+    /// A `return` statement, handled by the call to the function it returns from
+    Return(LoxResult),
 }
 
-#[derive(PartialEq, PartialOrd, Debug, Clone)]
+impl From<LoxRuntimeError> for Unwind {
+    fn from(e: LoxRuntimeError) -> Self {
+        Unwind::Error(e)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
 pub enum LoxResult {
     Number(f64),
     Str(String),
     Bool(bool),
     Nil,
+    NativeFn(NativeFn),
+    Function(Rc<LoxFunction>),
+}
+
+type NativeFunction = fn(&Interpreter, &[LoxResult]) -> Result<LoxResult, String>;
+
+/// Function implemented in Rust. Errors are reported as runtime errors on the call expression.
+#[derive(Clone)]
+pub struct NativeFn {
+    name: &'static str,
+    arity: usize,
+    function: NativeFunction,
+}
+
+impl NativeFn {
+    pub const fn new(name: &'static str, arity: usize, function: NativeFunction) -> NativeFn {
+        NativeFn {
+            name,
+            arity,
+            function,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// Natives are declared once by name, so two of them are the same function if their names match
+impl PartialEq for NativeFn {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Debug for NativeFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeFn")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+/// Function declared in Lox, along with the scope it was declared in
+pub struct LoxFunction {
+    name: String,
+    params: Vec<String>,
+    body: Rc<Vec<Stmt>>,
+    closure: Rc<RefCell<Environment>>,
+}
+
+impl LoxFunction {
+    /// Runs the body in a new scope binding the parameters, yielding what it returns or `nil`
+    fn call(
+        &self,
+        interpreter: &Interpreter,
+        arguments: Vec<LoxResult>,
+        (index, len): (usize, usize),
+    ) -> Result<LoxResult, LoxRuntimeError> {
+        let calls = interpreter.calls.get();
+        if calls >= interpreter.config.max_call_depth {
+            return Err(LoxRuntimeError {
+                code: "E108",
+                message: format!("Too many nested calls, more than {}", calls),
+                index,
+                len,
+            });
+        }
+        interpreter.calls.set(calls + 1);
+        let mut frame = Environment::with_capacity(self.params.len(), Some(self.closure.clone()));
+        for (param, argument) in self.params.iter().zip(arguments) {
+            frame.declare(param.clone(), Some(argument));
+        }
+        let frame = Rc::new(RefCell::new(frame));
+        let result = self
+            .body
+            .iter()
+            .try_for_each(|stmt| stmt.eval(interpreter, frame.clone()).map(|_| ()));
+        release(&frame);
+        interpreter.calls.set(calls);
+        match result {
+            Ok(()) => Ok(LoxResult::Nil),
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error(e)) => Err(e),
+        }
+    }
+}
+
+/// Functions hold the scope they are declared in, which holds them back, so neither would ever be
+/// freed. Called when `scope` is left by whoever holds it, to clear it when nothing else holds it
+/// but its own functions and nothing but the scope holds those. Functions that escaped, returned
+/// or stored in another scope, keep the scope and themselves alive.
+fn release(scope: &Rc<RefCell<Environment>>) {
+    let releasable = {
+        let environment = scope.borrow();
+        // Each function declared in the scope with the number of variables of the scope holding it
+        let mut functions: Vec<(&Rc<LoxFunction>, usize)> = Vec::new();
+        for value in environment.scope.values() {
+            if let Some(LoxResult::Function(function)) = value {
+                if !Rc::ptr_eq(&function.closure, scope) {
+                    continue;
+                }
+                match functions.iter_mut().find(|(f, _)| Rc::ptr_eq(f, function)) {
+                    Some((_, held)) => *held += 1,
+                    None => functions.push((function, 1)),
+                }
+            }
+        }
+        !functions.is_empty()
+            && Rc::strong_count(scope) == 1 + functions.len()
+            && functions
+                .iter()
+                .all(|(function, held)| Rc::strong_count(function) == *held)
+    };
+    if releasable {
+        scope.borrow_mut().scope.clear();
+    }
+}
+
+/// Every declaration creates a distinct function, even when running the same code twice
+impl PartialEq for LoxFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// Leaves the closure out, which may hold the function itself
+impl Debug for LoxFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoxFunction")
+            .field("name", &self.name)
+            .field("params", &self.params)
+            .finish()
+    }
+}
+
+/// Error message for calls passing `got` arguments to a function expecting `arity`
+fn check_arity(arity: usize, got: usize) -> Result<(), String> {
+    if arity == got {
+        Ok(())
+    } else {
+        Err(format!("Expected {} arguments but got {}", arity, got))
+    }
 }
 
 impl Display for LoxResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         match self {
-            Self::Number(n) => write!(f, "{}", n),
+            Self::Number(n) => write!(f, "{}", format_number(*n)),
             Self::Str(s) => write!(f, "{}", s),
             Self::Bool(b) => write!(f, "{}", b),
-            Self::Nil => write!(f, "Nil"),
+            Self::Nil => write!(f, "nil"),
+            Self::NativeFn(native) => write!(f, "<native fn {}>", native.name),
+            Self::Function(function) => write!(f, "<fn {}>", function.name),
         }
     }
 }
 
+/// Formats numbers like reference Lox: whole numbers without a decimal point, fractions with the
+/// shortest digits that read back as the same value, and `Infinity`, `-Infinity` and `NaN` for
+/// the special values. Magnitudes from 1e21 up switch to scientific notation instead of printing
+/// every digit.
+fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        String::from("NaN")
+    } else if n.is_infinite() {
+        String::from(if n > 0.0 { "Infinity" } else { "-Infinity" })
+    } else if n.abs() >= 1e21 {
+        format!("{:e}", n)
+    } else {
+        format!("{}", n)
+    }
+}
+
 #[derive(Debug)]
 pub struct LoxRuntimeError {
+    /// Stable identifier of the kind of error, explained by `diagnostics::explain`
+    code: &'static str,
     message: String,
     index: usize,
     len: usize,
 }
 
+impl LoxRuntimeError {
+    /// Like the `Display` form but locating the error by line and column within `code`
+    pub fn render(&self, code: &str) -> String {
+        self.render_at(code, 1)
+    }
+
+    /// Like `render` for `code` starting at line `first_line` of a larger source
+    pub fn render_at(&self, code: &str, first_line: usize) -> String {
+        diagnostics::locate(
+            "Error",
+            self.code,
+            &self.message,
+            self.index,
+            code,
+            first_line,
+        )
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    /// Byte index and length of the source the error points at
+    pub fn span(&self) -> (usize, usize) {
+        (self.index, self.len)
+    }
+}
+
 impl std::error::Error for LoxRuntimeError {}
 
 impl Display for LoxRuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Error: {} at {} until {}",
-            self.message, self.index, self.len
+            "Error[{}]: {} at {} until {}",
+            self.code, self.message, self.index, self.len
         )
     }
 }
 
 impl LoxResult {
-    fn get_type(&self) -> LoxType {
+    /// Quoted form of the value, with special characters in strings escaped. `Display` is the
+    /// unquoted form used by `print`.
+    pub fn repr(&self) -> String {
+        match self {
+            Self::Str(s) => {
+                let mut repr = String::with_capacity(s.len() + 2);
+                repr.push('"');
+                for c in s.chars() {
+                    match c {
+                        '"' => repr.push_str("\\\""),
+                        '\\' => repr.push_str("\\\\"),
+                        '\n' => repr.push_str("\\n"),
+                        '\t' => repr.push_str("\\t"),
+                        '\r' => repr.push_str("\\r"),
+                        '\0' => repr.push_str("\\0"),
+                        c if c.is_control() => repr.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                        c => repr.push(c),
+                    }
+                }
+                repr.push('"');
+                repr
+            }
+            value => value.to_string(),
+        }
+    }
+
+    pub fn get_type(&self) -> LoxType {
         match self {
             Self::Number(_) => LoxType::Number,
             Self::Str(_) => LoxType::Str,
             Self::Bool(_) => LoxType::Bool,
             Self::Nil => LoxType::Nil,
+            Self::NativeFn(_) | Self::Function(_) => LoxType::Function,
         }
     }
 
+    /// Whether the value counts as true for conditions and logical operators. As in standard Lox
+    /// only `nil` and `false` are falsy, every other value is truthy, including `0` and `""`.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Self::Nil | Self::Bool(false))
+    }
+
     fn unwrap_number(self) -> f64 {
-        match self {
-            Self::Number(n) => n,
-            _ => panic!("LoxResult is not a number"),
-        }
+        self.try_into().expect("LoxResult is not a number")
     }
 
     fn unwrap_string(self) -> String {
-        match self {
-            Self::Str(n) => n.clone(),
-            _ => panic!("LoxResult is not a number"),
+        self.try_into().expect("LoxResult is not a string")
+    }
+}
+
+impl From<f64> for LoxResult {
+    fn from(n: f64) -> Self {
+        Self::Number(n)
+    }
+}
+
+impl From<i64> for LoxResult {
+    fn from(n: i64) -> Self {
+        Self::Number(n as f64)
+    }
+}
+
+impl From<bool> for LoxResult {
+    fn from(b: bool) -> Self {
+        Self::Bool(b)
+    }
+}
+
+impl From<String> for LoxResult {
+    fn from(s: String) -> Self {
+        Self::Str(s)
+    }
+}
+
+impl From<&str> for LoxResult {
+    fn from(s: &str) -> Self {
+        Self::Str(s.into())
+    }
+}
+
+impl From<()> for LoxResult {
+    fn from(_: ()) -> Self {
+        Self::Nil
+    }
+}
+
+/// The error has no span since the value is not tied to an expression, callers with one should
+/// report their own
+impl TryFrom<LoxResult> for f64 {
+    type Error = LoxRuntimeError;
+
+    fn try_from(value: LoxResult) -> Result<Self, Self::Error> {
+        match value {
+            LoxResult::Number(n) => Ok(n),
+            value => Err(LoxRuntimeError {
+                code: "E101",
+                message: format!("Expected a number but got a {}", value.get_type()),
+                index: 0,
+                len: 0,
+            }),
+        }
+    }
+}
+
+impl TryFrom<LoxResult> for String {
+    type Error = LoxRuntimeError;
+
+    fn try_from(value: LoxResult) -> Result<Self, Self::Error> {
+        match value {
+            LoxResult::Str(s) => Ok(s),
+            value => Err(LoxRuntimeError {
+                code: "E101",
+                message: format!("Expected a string but got a {}", value.get_type()),
+                index: 0,
+                len: 0,
+            }),
         }
     }
 }
 
 #[derive(PartialEq, Debug)]
-enum LoxType {
+pub enum LoxType {
     Number,
     Str,
     Bool,
     Nil,
+    Function,
+}
+
+impl Display for LoxType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Number => write!(f, "number"),
+            Self::Str => write!(f, "string"),
+            Self::Bool => write!(f, "bool"),
+            Self::Nil => write!(f, "nil"),
+            Self::Function => write!(f, "function"),
+        }
+    }
+}
+
+type BinaryOperation = fn(LoxResult, LoxResult) -> LoxResult;
+
+/// Dispatch table for binary operators, keyed by the operator and the types of both operands.
+/// Combinations without an entry are type errors, reported by the caller.
+fn binary_operation(operator: &BinOp, left: &LoxType, right: &LoxType) -> Option<BinaryOperation> {
+    use LoxType::*;
+    let operation: BinaryOperation = match (operator, left, right) {
+        (BinOp::Comma, _, _) => |_, r| r,
+        (BinOp::Sum, Number, Number) => {
+            |l, r| LoxResult::Number(l.unwrap_number() + r.unwrap_number())
+        }
+        (BinOp::Sum, Str, Str) => |l, r| LoxResult::Str(l.unwrap_string() + &r.unwrap_string()),
+        // Any other value is concatenated in the form `print` shows it
+        (BinOp::Sum, Str, _) | (BinOp::Sum, _, Str) => |l, r| LoxResult::Str(format!("{}{}", l, r)),
+        (BinOp::Substraction, Number, Number) => {
+            |l, r| LoxResult::Number(l.unwrap_number() - r.unwrap_number())
+        }
+        (BinOp::Product, Number, Number) => {
+            |l, r| LoxResult::Number(l.unwrap_number() * r.unwrap_number())
+        }
+        (BinOp::Division, Number, Number) => {
+            |l, r| LoxResult::Number(l.unwrap_number() / r.unwrap_number())
+        }
+        // Values of different types are never equal, the rest of operators can't mix types
+        (BinOp::Equals, _, _) => |l, r| LoxResult::Bool(l == r),
+        (BinOp::NotEquals, _, _) => |l, r| LoxResult::Bool(l != r),
+        (_, l, r) if l != r => return None,
+        // Only numbers and strings have an order
+        (_, Bool, _) | (_, Nil, _) | (_, Function, _) => return None,
+        (BinOp::GreaterThan, _, _) => {
+            |l, r| LoxResult::Bool(order(l, r) == Some(Ordering::Greater))
+        }
+        (BinOp::GreaterThanEquals, _, _) => |l, r| {
+            LoxResult::Bool(matches!(
+                order(l, r),
+                Some(Ordering::Greater | Ordering::Equal)
+            ))
+        },
+        (BinOp::LessThan, _, _) => |l, r| LoxResult::Bool(order(l, r) == Some(Ordering::Less)),
+        (BinOp::LessThanEquals, _, _) => |l, r| {
+            LoxResult::Bool(matches!(
+                order(l, r),
+                Some(Ordering::Less | Ordering::Equal)
+            ))
+        },
+        _ => return None,
+    };
+    Some(operation)
+}
+
+/// Numbers by value and strings lexicographically. `NaN` isn't ordered, every comparison with it
+/// is false.
+fn order(l: LoxResult, r: LoxResult) -> Option<Ordering> {
+    match (l, r) {
+        (LoxResult::Number(l), LoxResult::Number(r)) => l.partial_cmp(&r),
+        (LoxResult::Str(l), LoxResult::Str(r)) => Some(l.cmp(&r)),
+        (l, r) => unreachable!("{:?} and {:?} have no order", l, r),
+    }
+}
+
+/// Whether the operator compares the order of its operands
+fn is_ordering(operator: &BinOp) -> bool {
+    matches!(
+        operator,
+        BinOp::GreaterThan | BinOp::GreaterThanEquals | BinOp::LessThan | BinOp::LessThanEquals
+    )
 }
 
 impl Interpretable for Stmt {
+    type Error = Unwind;
+
     fn eval(
         &self,
+        interpreter: &Interpreter,
         environment: Rc<RefCell<Environment>>,
-    ) -> std::result::Result<LoxResult, LoxRuntimeError> {
+    ) -> std::result::Result<LoxResult, Unwind> {
+        if let (Some(covered), Some(span)) = (&interpreter.covered, self.span()) {
+            covered.borrow_mut().insert(span);
+        }
         match self {
-            Stmt::Expression(e) => e.eval(environment),
+            Stmt::Expression(e) => Ok(e.eval(interpreter, environment)?),
             Stmt::Print(e) => {
-                e.eval(environment).map(|r| println!("{}", r))?;
+                let value = e.eval(interpreter, environment)?;
+                interpreter.print(&value);
                 Ok(LoxResult::Nil)
             }
-            Stmt::Variable(e, v) => {
-                let value = match v {
-                    Some(e) => Some(e.eval(environment.clone())?),
+            Stmt::Variable {
+                name, initializer, ..
+            } => {
+                let value = match initializer {
+                    Some(e) => Some(e.eval(interpreter, environment.clone())?),
                     _ => None,
                 };
-                environment.borrow_mut().declare(e.clone(), value);
+                environment.borrow_mut().declare(name.clone(), value);
                 Ok(LoxResult::Nil)
             }
             Stmt::Block(stmts) => {
-                let scoped_env = Rc::new(RefCell::new(Environment::with_parent(environment)));
-                for stmt in stmts {
-                    stmt.eval(scoped_env.clone())?;
+                let scoped_env = block_scope(stmts, environment);
+                let result = stmts
+                    .iter()
+                    .try_for_each(|stmt| stmt.eval(interpreter, scoped_env.clone()).map(|_| ()));
+                release(&scoped_env);
+                result.map(|()| LoxResult::Nil)
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let branch = if condition
+                    .eval(interpreter, environment.clone())?
+                    .is_truthy()
+                {
+                    Some(then_branch)
+                } else {
+                    else_branch.as_ref()
+                };
+                if let Some(branch) = branch {
+                    branch.eval(interpreter, environment)?;
+                }
+                Ok(LoxResult::Nil)
+            }
+            Stmt::While { condition, body } => {
+                let mut loop_scope = LoopScope::default();
+                while condition
+                    .eval(interpreter, environment.clone())?
+                    .is_truthy()
+                {
+                    interpreter.check_interrupt(condition)?;
+                    match body.as_ref() {
+                        Stmt::Block(stmts) => {
+                            loop_scope.run(stmts, interpreter, environment.clone())?
+                        }
+                        body => {
+                            body.eval(interpreter, environment.clone())?;
+                        }
+                    }
                 }
                 Ok(LoxResult::Nil)
             }
+            Stmt::Function {
+                name, params, body, ..
+            } => {
+                let function = LoxFunction {
+                    name: name.clone(),
+                    params: params.clone(),
+                    body: body.clone(),
+                    closure: environment.clone(),
+                };
+                environment
+                    .borrow_mut()
+                    .declare(name.clone(), Some(LoxResult::Function(Rc::new(function))));
+                Ok(LoxResult::Nil)
+            }
+            Stmt::Return { value, .. } => {
+                let value = match value {
+                    Some(value) => value.eval(interpreter, environment)?,
+                    None => LoxResult::Nil,
+                };
+                Err(Unwind::Return(value))
+            }
+        }
+    }
+}
+
+/// New scope for running `stmts`, pre-sized for the variables they declare
+fn block_scope(stmts: &[Stmt], parent: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+    let declarations = stmts
+        .iter()
+        .filter(|s| matches!(s, Stmt::Variable { .. }))
+        .count();
+    Rc::new(RefCell::new(Environment::with_capacity(
+        declarations,
+        Some(parent),
+    )))
+}
+
+/// Scope of a block run on every iteration of a loop, cleared and reused by the next one instead
+/// of allocating a new scope each time. Blocks directly within the block keep theirs too, which
+/// covers the body of `for` loops, nested in the block running the increment.
+#[derive(Default)]
+struct LoopScope {
+    scope: Option<Rc<RefCell<Environment>>>,
+    blocks: Vec<LoopScope>,
+}
+
+impl LoopScope {
+    fn run(
+        &mut self,
+        stmts: &[Stmt],
+        interpreter: &Interpreter,
+        parent: Rc<RefCell<Environment>>,
+    ) -> Result<(), Unwind> {
+        let scope = match self.scope.take() {
+            Some(scope) => {
+                scope.borrow_mut().parent = Some(parent);
+                scope
+            }
+            None => block_scope(stmts, parent),
+        };
+        self.blocks.resize_with(stmts.len(), LoopScope::default);
+        let result = stmts
+            .iter()
+            .zip(&mut self.blocks)
+            .try_for_each(|(stmt, block)| match stmt {
+                Stmt::Block(stmts) => block.run(stmts, interpreter, scope.clone()),
+                stmt => stmt.eval(interpreter, scope.clone()).map(|_| ()),
+            });
+        release(&scope);
+        // Closures that escaped the block still hold on to the scope, the next iteration gets a
+        // new one so they keep the variables they captured. Idle scopes let go of their parent,
+        // so the blocks kept within this one don't count as holding on to it.
+        if Rc::strong_count(&scope) == 1 {
+            {
+                let mut environment = scope.borrow_mut();
+                environment.scope.clear();
+                environment.parent = None;
+            }
+            self.scope = Some(scope);
         }
+        result
     }
 }
 
 impl Interpretable for Expr {
+    type Error = LoxRuntimeError;
+
     fn eval(
         &self,
+        interpreter: &Interpreter,
         env: Rc<RefCell<Environment>>,
     ) -> std::result::Result<LoxResult, LoxRuntimeError> {
         let res = match self {
@@ -169,9 +1011,10 @@ impl Interpretable for Expr {
                     _ => Ok(LoxResult::Nil),
                 },
                 _ => Err(LoxRuntimeError {
-                    message: format!("The variable was not initialized before usage"),
+                    code: "E102",
+                    message: String::from("The variable was not initialized before usage"),
                     index: *index,
-                    len: *index + *len,
+                    len: *len,
                 }),
             }?,
             Self::Assign {
@@ -180,10 +1023,11 @@ impl Interpretable for Expr {
                 key,
                 value,
             } => {
-                let res = value.eval(env.clone())?;
+                let res = value.eval(interpreter, env.clone())?;
                 let mut env = env.borrow_mut();
-                if let Some(_) = env.get(key) {
+                if env.get(key).is_some() {
                     env.set(key, res.clone()).map_err(|_| LoxRuntimeError {
+                        code: "E102",
                         message: format!("Variable \"{}\" not initialized", key),
                         index: *index,
                         len: *len,
@@ -191,17 +1035,32 @@ impl Interpretable for Expr {
                     res
                 } else {
                     Err(LoxRuntimeError {
+                        code: "E102",
                         message: format!("Variable \"{}\" was not initialized", key),
                         index: *index,
                         len: *len,
                     })?
                 }
             }
+            Self::Logical {
+                left,
+                operator,
+                right,
+                index: _,
+                len: _,
+            } => {
+                let left = left.eval(interpreter, env.clone())?;
+                // Yields the operand that decided the result, skipping the right one if possible
+                match (operator, left.is_truthy()) {
+                    (LogicalOp::Or, true) | (LogicalOp::And, false) => left,
+                    _ => right.eval(interpreter, env)?,
+                }
+            }
             Self::Literal {
                 value,
                 index: _,
                 len: _,
-            } => match value {
+            } => match value.as_ref() {
                 Literal::Number(n) => LoxResult::Number(*n),
                 Literal::Str(n) => LoxResult::Str(n.clone()),
                 Literal::True => LoxResult::Bool(true),
@@ -213,100 +1072,1387 @@ impl Interpretable for Expr {
                 right,
                 index,
                 len,
-            } => {
-                let right = right.eval(env)?;
-
-                match operator {
-                    crate::ast::UnaryOp::LogicNegate => match right {
-                        LoxResult::Bool(b) => LoxResult::Bool(!b),
-                        _ => Err(LoxRuntimeError {
-                            message: format!("Cant negate type {:?}", right.get_type()),
-                            index: *index,
-                            len: *len,
-                        })?,
-                    },
-                    crate::ast::UnaryOp::Negate => match right {
-                        LoxResult::Number(n) => LoxResult::Number(-n),
-                        _ => Err(LoxRuntimeError {
-                            message: format!("Cant negate type {:?}", right.get_type()),
-                            index: *index,
-                            len: *len,
-                        })?,
-                    },
-                }
-            }
+            } => unary(operator, right.eval(interpreter, env)?, *index, *len)?,
             Self::Grouping {
                 expr,
                 index: _,
                 len: _,
-            } => expr.eval(env)?,
-            Self::Ternary {
-                condition,
+            } => expr.eval(interpreter, env)?,
+            Self::Binary { left, .. } if is_long_chain(left, &interpreter.config) => {
+                eval_operators(self, interpreter, env)?
+            }
+            Self::Binary {
                 left,
                 right,
+                operator,
+                index,
+                len,
+            } => {
+                // Operands run left to right, which the comma operator relies on for the side
+                // effects of the left one to happen first
+                let l = left.eval(interpreter, env.clone())?;
+                let r = right.eval(interpreter, env)?;
+                binary(operator, l, r, *index, *len, &interpreter.config)?
+            }
+            Self::Call {
+                callee,
+                arguments,
                 index,
                 len,
             } => {
-                let condition = condition.eval(env.clone())?;
-                let condition = match condition {
-                    LoxResult::Bool(b) => b,
-                    r => Err(LoxRuntimeError {
-                        message: format!(
-                        "The condition of a ternary operator must resolve to a boolean but was {:?}",
-                        r.get_type()
-                    ), index: *index, len: *len})?,
+                let callee = callee.eval(interpreter, env.clone())?;
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| argument.eval(interpreter, env.clone()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let error = |code| {
+                    move |message| LoxRuntimeError {
+                        code,
+                        message,
+                        index: *index,
+                        len: *len,
+                    }
                 };
-                if condition {
-                    left.eval(env)?
-                } else {
-                    right.eval(env)?
+                match callee {
+                    LoxResult::NativeFn(native) => {
+                        check_arity(native.arity, arguments.len()).map_err(error("E106"))?;
+                        (native.function)(interpreter, &arguments).map_err(error("E107"))?
+                    }
+                    LoxResult::Function(function) => {
+                        check_arity(function.params.len(), arguments.len())
+                            .map_err(error("E106"))?;
+                        function.call(interpreter, arguments, (*index, *len))?
+                    }
+                    value => Err(error("E105")(format!(
+                        "Can only call functions, {} is not callable",
+                        value.get_type()
+                    )))?,
                 }
             }
-            Self::Binary {
+            Self::Ternary {
+                condition,
                 left,
                 right,
+                index: _,
+                len: _,
+            } => {
+                if condition.eval(interpreter, env.clone())?.is_truthy() {
+                    left.eval(interpreter, env)?
+                } else {
+                    right.eval(interpreter, env)?
+                }
+            }
+        };
+        Ok(res)
+    }
+}
+
+/// Whether `expr` starts a chain of at least `Config::long_chain` binary operators, as the parser
+/// builds for `1 + 2 + ... + n`
+fn is_long_chain(mut expr: &Expr, config: &Config) -> bool {
+    let length = match config.long_chain {
+        Some(length) => length,
+        None => return false,
+    };
+    for _ in 0..length {
+        match expr {
+            Expr::Binary { left, .. } => expr = left,
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Evaluates a tree of unary, binary and grouping expressions with an explicit stack instead of
+/// recursion, so long operator chains don't cost a Rust frame per operator. Any other expression
+/// within the tree is evaluated recursively as usual.
+fn eval_operators(
+    expr: &Expr,
+    interpreter: &Interpreter,
+    env: Rc<RefCell<Environment>>,
+) -> Result<LoxResult, LoxRuntimeError> {
+    enum Step<'a> {
+        Eval(&'a Expr),
+        // Applies the operator of the expression to the operands on top of `values`
+        Apply(&'a Expr),
+    }
+    let mut steps = vec![Step::Eval(expr)];
+    let mut values = Vec::new();
+    while let Some(step) = steps.pop() {
+        match step {
+            Step::Eval(expr @ Expr::Binary { left, right, .. }) => {
+                // Pushed in reverse so the left operand runs first, as in `Expr::eval`
+                steps.push(Step::Apply(expr));
+                steps.push(Step::Eval(right));
+                steps.push(Step::Eval(left));
+            }
+            Step::Eval(expr @ Expr::Unary { right, .. }) => {
+                steps.push(Step::Apply(expr));
+                steps.push(Step::Eval(right));
+            }
+            Step::Eval(Expr::Grouping { expr, .. }) => steps.push(Step::Eval(expr)),
+            Step::Eval(expr) => values.push(expr.eval(interpreter, env.clone())?),
+            Step::Apply(Expr::Binary {
                 operator,
                 index,
                 len,
-            } => {
-                let l = left.eval(env.clone())?;
-                let r = right.eval(env)?;
-                if l.get_type() != r.get_type() && operator != &BinOp::Comma {
-                    Err(LoxRuntimeError {
-                        message: format!(
-                            "Cant operate on {:?} and {:?}",
-                            l.get_type(),
-                            r.get_type(),
-                        ),
-                        index: *index,
-                        len: *len,
-                    })?;
-                }
-                let res = match operator {
-                    BinOp::Sum => match l.get_type() {
-                        LoxType::Number => LoxResult::Number(l.unwrap_number() + r.unwrap_number()),
-                        LoxType::Str => LoxResult::Str(l.unwrap_string() + &r.unwrap_string()),
-                        n => Err(LoxRuntimeError {
-                            message: format!("Can't perform Sum on {:?}", n),
-                            index: *index,
-                            len: *len,
-                        })?,
+                ..
+            }) => {
+                let r = values.pop().expect("Missing right operand");
+                let l = values.pop().expect("Missing left operand");
+                values.push(binary(operator, l, r, *index, *len, &interpreter.config)?);
+            }
+            Step::Apply(Expr::Unary {
+                operator,
+                index,
+                len,
+                ..
+            }) => {
+                let right = values.pop().expect("Missing operand");
+                values.push(unary(operator, right, *index, *len)?);
+            }
+            Step::Apply(_) => unreachable!("Only operators are applied"),
+        }
+    }
+    Ok(values.pop().expect("Missing result"))
+}
+
+fn unary(
+    operator: &UnaryOp,
+    right: LoxResult,
+    index: usize,
+    len: usize,
+) -> Result<LoxResult, LoxRuntimeError> {
+    let res = match operator {
+        UnaryOp::LogicNegate => LoxResult::Bool(!right.is_truthy()),
+        UnaryOp::Negate => match right {
+            LoxResult::Number(n) => LoxResult::Number(-n),
+            _ => Err(LoxRuntimeError {
+                code: "E101",
+                message: format!("Cant negate type {:?}", right.get_type()),
+                index,
+                len,
+            })?,
+        },
+        UnaryOp::AssertNotNil => match right {
+            LoxResult::Nil => Err(LoxRuntimeError {
+                code: "E104",
+                message: String::from("Unexpected nil"),
+                index,
+                len,
+            })?,
+            value => value,
+        },
+    };
+    Ok(res)
+}
+
+fn binary(
+    operator: &BinOp,
+    l: LoxResult,
+    r: LoxResult,
+    index: usize,
+    len: usize,
+    config: &Config,
+) -> Result<LoxResult, LoxRuntimeError> {
+    let (l, r) = if config.coerce {
+        coerce(operator, l, r, index, len)?
+    } else {
+        (l, r)
+    };
+    // Lox numbers are floats, but infinity and NaN would only hide the mistake. Both zeros
+    // compare equal, so `-0` is caught too.
+    if let (BinOp::Division, LoxResult::Number(_), LoxResult::Number(divisor)) = (operator, &l, &r)
+    {
+        if *divisor == 0.0 {
+            Err(LoxRuntimeError {
+                code: "E103",
+                message: String::from("Division by zero"),
+                index,
+                len,
+            })?
+        }
+    }
+    let (left_type, right_type) = (l.get_type(), r.get_type());
+    match binary_operation(operator, &left_type, &right_type) {
+        Some(operation) => Ok(operation(l, r)),
+        None if is_ordering(operator) => Err(LoxRuntimeError {
+            code: "E101",
+            message: format!("Can't compare {:?} and {:?}", left_type, right_type),
+            index,
+            len,
+        }),
+        None if left_type != right_type => Err(LoxRuntimeError {
+            code: "E101",
+            message: format!("Cant operate on {:?} and {:?}", left_type, right_type),
+            index,
+            len,
+        }),
+        None => Err(LoxRuntimeError {
+            code: "E101",
+            message: format!("Can't perform {:?} on {:?}", operator, left_type),
+            index,
+            len,
+        }),
+    }
+}
+
+/// Operands of an arithmetic operator with strings turned into the numbers they spell, unless both
+/// are strings being concatenated. Operands that aren't numbers or strings are left as they are.
+fn coerce(
+    operator: &BinOp,
+    l: LoxResult,
+    r: LoxResult,
+    index: usize,
+    len: usize,
+) -> Result<(LoxResult, LoxResult), LoxRuntimeError> {
+    let arithmetic = matches!(
+        operator,
+        BinOp::Sum | BinOp::Substraction | BinOp::Product | BinOp::Division
+    );
+    let coercible = |value: &LoxResult| matches!(value, LoxResult::Number(_) | LoxResult::Str(_));
+    if !arithmetic || !coercible(&l) || !coercible(&r) {
+        return Ok((l, r));
+    }
+    if let (BinOp::Sum, LoxResult::Str(_), LoxResult::Str(_)) = (operator, &l, &r) {
+        return Ok((l, r));
+    }
+    let to_number = |value: LoxResult| match value {
+        LoxResult::Str(s) => match s.trim().parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(LoxResult::Number(n)),
+            _ => Err(LoxRuntimeError {
+                code: "E101",
+                message: format!("Can't coerce {} to a number", LoxResult::Str(s).repr()),
+                index,
+                len,
+            }),
+        },
+        value => Ok(value),
+    };
+    Ok((to_number(l)?, to_number(r)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        collections::HashMap,
+        convert::{TryFrom, TryInto},
+        io::Write,
+        rc::Rc,
+        sync::{
+            atomic::{self, AtomicBool},
+            Arc,
+        },
+    };
+
+    use super::{
+        eval_operators, format_number, Environment, FileSystem, Interpreter, LoxResult,
+        LoxRuntimeError,
+    };
+    use crate::{
+        ast::{Expr, Stmt},
+        config::Config,
+        lexer::{tokenize, TokenKind},
+        natives,
+        parser::{parse, parse_expression},
+    };
+
+    /// Cloneable sink so a test can keep a handle to what the interpreter writes
+    #[derive(Clone, Default)]
+    struct Capture(Rc<RefCell<Vec<u8>>>);
+
+    impl Capture {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Parses with the comma operator on, as these tests are about evaluating every operator
+    fn parse_config() -> Config {
+        Config {
+            comma_operator: true,
+            ..Config::default()
+        }
+    }
+
+    fn parse_code(code: &str) -> Vec<Stmt> {
+        let mut tokens = tokenize(code)
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .peekable();
+        parse(&mut tokens, &parse_config()).unwrap()
+    }
+
+    fn parse_expr(code: &str) -> Expr {
+        let mut tokens = tokenize(code)
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .peekable();
+        parse_expression(&mut tokens, &parse_config()).unwrap()
+    }
+
+    /// Evaluates a single expression on a fresh interpreter
+    fn evaluate(code: &str) -> Result<LoxResult, LoxRuntimeError> {
+        Interpreter::new().evaluate(&parse_expr(code))
+    }
+
+    fn run(code: &str, interpreter: &Interpreter) {
+        interpreter.interpret(&parse_code(code)).unwrap();
+    }
+
+    #[test]
+    fn presized_block_scopes() {
+        let interpreter = Interpreter::new();
+        run(
+            "var a = 1; var b = 0; { var a = 2; var c = 3; b = a + c; }",
+            &interpreter,
+        );
+        let env = interpreter.globals.borrow();
+        assert_eq!(env.get("a"), Some(Some(LoxResult::Number(1.0))));
+        assert_eq!(env.get("b"), Some(Some(LoxResult::Number(5.0))));
+        assert_eq!(env.get("c"), None);
+
+        let scoped = Environment::with_capacity(8, None);
+        assert!(scoped.scope.capacity() >= 8);
+    }
+
+    #[test]
+    fn block_scopes_are_released() {
+        let interpreter = Interpreter::new();
+        run("{ var a = 1; { var b = a; } }", &interpreter);
+        // Nested scopes only point to their parents, so once the blocks finish nothing but the
+        // interpreter holds on to the global scope
+        assert_eq!(Rc::strong_count(&interpreter.globals), 1);
+    }
+
+    #[test]
+    fn display_scalars() {
+        // Strings are the only values whose quoted form differs from what `print` shows
+        for (value, display, repr) in [
+            (LoxResult::Bool(true), "true", "true"),
+            (LoxResult::Bool(false), "false", "false"),
+            (LoxResult::Nil, "nil", "nil"),
+            (LoxResult::Number(3.0), "3", "3"),
+            (LoxResult::Number(-0.5), "-0.5", "-0.5"),
+            (LoxResult::from("a\"b"), "a\"b", "\"a\\\"b\""),
+        ] {
+            assert_eq!(value.to_string(), display);
+            assert_eq!(value.repr(), repr);
+        }
+
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run(
+            "print true; print 1 == 2; print nil; print 2.5; print \"s\";",
+            &interpreter,
+        );
+        assert_eq!(output.contents(), "true\nfalse\nnil\n2.5\ns\n");
+    }
+
+    #[test]
+    fn print_nil() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run("print nil; var a; print a;", &interpreter);
+        assert_eq!(output.contents(), "nil\nnil\n");
+    }
+
+    #[test]
+    fn format_special_numbers() {
+        assert_eq!(format_number(f64::INFINITY), "Infinity");
+        assert_eq!(format_number(f64::NEG_INFINITY), "-Infinity");
+        assert_eq!(format_number(f64::NAN), "NaN");
+        assert_eq!(format_number(1e20), "100000000000000000000");
+        assert_eq!(format_number(1.5e300), "1.5e300");
+        assert_eq!(format_number(-2.5), "-2.5");
+        assert_eq!(format_number(-0.0), "-0");
+    }
+
+    #[test]
+    fn print_numbers() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run(
+            "print 1; print 1.5; print 100000000; print 0.1 + 0.2; print -0;",
+            &interpreter,
+        );
+        assert_eq!(
+            output.contents(),
+            "1\n1.5\n100000000\n0.30000000000000004\n-0\n"
+        );
+    }
+
+    #[test]
+    fn print_to_captured_output() {
+        let output = Capture::default();
+        let error_output = Capture::default();
+        let interpreter = Interpreter::new()
+            .with_output(output.clone())
+            .with_error_output(error_output.clone());
+        run("print 1 + 2; print \"a\";", &interpreter);
+        assert_eq!(output.contents(), "3\na\n");
+
+        interpreter.report(&std::fmt::Error);
+        assert_eq!(
+            error_output.contents(),
+            "an error occurred when formatting an argument\n"
+        );
+    }
+
+    #[test]
+    fn string_concatenation_chains() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run(
+            "var a = \"a\"; var b = \"b\"; print a + b + \"\" + a + b; print a;",
+            &interpreter,
+        );
+        assert_eq!(output.contents(), "abab\na\n");
+
+        let chain = vec!["\"xy\""; 200].join(" + ");
+        run(&format!("var s = {};", chain), &interpreter);
+        assert_eq!(
+            interpreter.globals.borrow().get("s"),
+            Some(Some(LoxResult::Str("xy".repeat(200))))
+        );
+    }
+
+    #[test]
+    fn declare_and_print_variables() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run(
+            "var x = 1; print x; var y; y = 3; print x + y;",
+            &interpreter,
+        );
+        assert_eq!(output.contents(), "1\n4\n");
+    }
+
+    #[test]
+    fn if_else_statements() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run("if (true) print 1; else print 2;", &interpreter);
+        run("if (1 > 2) print 3; else print 4;", &interpreter);
+        run("if (false) print 5;", &interpreter);
+        run("if (true) if (false) print 6; else print 7;", &interpreter);
+        run("if (0) print 8; if (nil) print 9;", &interpreter);
+        assert_eq!(output.contents(), "1\n4\n7\n8\n");
+    }
+
+    #[test]
+    fn while_loops() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run(
+            "var i = 0; while (i < 3) { print i; i = i + 1; }",
+            &interpreter,
+        );
+        run("while (false) print \"never\";", &interpreter);
+        // Loops until the condition becomes nil
+        run(
+            "var n = 2; while (n) { print n; n = (n > 1 ? n - 1 : nil); }",
+            &interpreter,
+        );
+        assert_eq!(output.contents(), "0\n1\n2\n2\n1\n");
+    }
+
+    #[test]
+    fn truthiness() {
+        let one = LoxResult::Number(1.0);
+        let two = LoxResult::Number(2.0);
+        assert_eq!(evaluate("nil ? 1 : 2").unwrap(), two);
+        assert_eq!(evaluate("false ? 1 : 2").unwrap(), two);
+        assert_eq!(evaluate("0 ? 1 : 2").unwrap(), one);
+        assert_eq!(evaluate("\"\" ? 1 : 2").unwrap(), one);
+        assert_eq!(evaluate("\"hello\" ? 1 : 2").unwrap(), one);
+        assert_eq!(evaluate("!0").unwrap(), LoxResult::Bool(false));
+        assert_eq!(evaluate("!\"\"").unwrap(), LoxResult::Bool(false));
+    }
+
+    #[test]
+    fn for_loops() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run("for (var i = 0; i < 3; i = i + 1) print i;", &interpreter);
+        run(
+            "var j = 0; for (; j < 2;) { print j; j = j + 1; }",
+            &interpreter,
+        );
+        assert_eq!(output.contents(), "0\n1\n2\n0\n1\n");
+        // The loop variable is scoped to the loop
+        let globals = interpreter.globals_snapshot();
+        assert!(globals.contains_key("j"));
+        assert!(!globals.contains_key("i"));
+    }
+
+    #[test]
+    fn native_functions() {
+        match evaluate("clock()").unwrap() {
+            LoxResult::Number(n) => assert!(n > 0.0),
+            r => panic!("Expected a number but got {:?}", r),
+        }
+        let interpreter = Interpreter::new().with_clock(|| 1234.5);
+        assert_eq!(
+            interpreter.evaluate(&parse_expr("clock() + 1")).unwrap(),
+            LoxResult::Number(1235.5)
+        );
+        assert_eq!(evaluate("clock").unwrap().to_string(), "<native fn clock>");
+        assert_eq!(evaluate("clock == clock").unwrap(), LoxResult::Bool(true));
+        assert!(evaluate("clock < clock").is_err());
+
+        let err = evaluate("1()").unwrap_err();
+        assert_eq!(
+            err.message,
+            "Can only call functions, number is not callable"
+        );
+        assert_eq!((err.index, err.len), (0, 3));
+        let err = evaluate("clock(1)").unwrap_err();
+        assert_eq!(err.message, "Expected 0 arguments but got 1");
+    }
+
+    #[test]
+    fn round_to_native() {
+        for (code, rounded) in [
+            ("round_to(3.14159, 2)", "3.14"),
+            // Halfway cases round away from zero
+            ("round_to(2.5, 0)", "3"),
+            ("round_to(-2.5, 0)", "-3"),
+            ("round_to(1234.5, 0)", "1235"),
+            ("round_to(0.1, 400)", "0.1"),
+        ] {
+            let value = evaluate(code).unwrap();
+            assert!(matches!(value, LoxResult::Number(_)), "{}", code);
+            assert_eq!(value.to_string(), rounded, "{}", code);
+        }
+
+        for (code, message) in [
+            (
+                "round_to(\"1\", 2)",
+                "round_to expects two numbers but got string and number",
+            ),
+            (
+                "round_to(1, nil)",
+                "round_to expects two numbers but got number and nil",
+            ),
+            (
+                "round_to(1, -1)",
+                "round_to expects a whole number of digits of 0 or more but got -1",
+            ),
+            (
+                "round_to(1, 1.5)",
+                "round_to expects a whole number of digits of 0 or more but got 1.5",
+            ),
+        ] {
+            let err = evaluate(code).unwrap_err();
+            assert_eq!((err.code(), err.message.as_str()), ("E107", message));
+        }
+    }
+
+    #[test]
+    fn seeded_random_numbers() {
+        let numbers = |interpreter: &Interpreter| -> Vec<f64> {
+            (0..5)
+                .map(
+                    |_| match interpreter.evaluate(&parse_expr("random()")).unwrap() {
+                        LoxResult::Number(n) => n,
+                        r => panic!("Expected a number but got {:?}", r),
                     },
-                    BinOp::Substraction => LoxResult::Number(l.unwrap_number() - r.unwrap_number()),
-                    BinOp::Product => LoxResult::Number(l.unwrap_number() * r.unwrap_number()),
-                    BinOp::Division => LoxResult::Number(l.unwrap_number() / r.unwrap_number()),
-                    BinOp::Equals => LoxResult::Bool(l == r),
-                    BinOp::GreaterThan => LoxResult::Bool(l > r),
-                    BinOp::GreaterThanEquals => LoxResult::Bool(l >= r),
-                    BinOp::LessThan => LoxResult::Bool(l < r),
-                    BinOp::LessThanEquals => LoxResult::Bool(l <= r),
-                    BinOp::NotEquals => LoxResult::Bool(l != r),
-                    BinOp::Comma => r,
-                };
+                )
+                .collect()
+        };
+        let first = numbers(&Interpreter::new().with_seed(42));
+        assert_eq!(first, numbers(&Interpreter::new().with_seed(42)));
+        assert_ne!(first, numbers(&Interpreter::new().with_seed(43)));
+        assert!(first.iter().all(|n| (0.0..1.0).contains(n)));
+
+        // Seeding from the program restarts the sequence
+        let interpreter = Interpreter::new();
+        run("seed(7);", &interpreter);
+        let seeded = numbers(&interpreter);
+        run("seed(7);", &interpreter);
+        assert_eq!(seeded, numbers(&interpreter));
+
+        let err = evaluate("seed(\"7\")").unwrap_err();
+        assert_eq!(
+            (err.code(), err.message.as_str()),
+            ("E107", "seed expects a number but got string")
+        );
+    }
+
+    /// Files kept in memory, shared between clones so a test can look at what a program wrote
+    #[derive(Clone, Default)]
+    struct MemoryFiles(Rc<RefCell<HashMap<String, String>>>);
+
+    impl FileSystem for MemoryFiles {
+        fn read(&self, path: &str) -> std::io::Result<String> {
+            self.0
+                .borrow()
+                .get(path)
+                .cloned()
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "No such file"))
+        }
+
+        fn write(&self, path: &str, contents: &str) -> std::io::Result<()> {
+            self.0.borrow_mut().insert(path.into(), contents.into());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn file_natives() {
+        let files = MemoryFiles::default();
+        files.write("greeting.txt", "hello").unwrap();
+        let interpreter = Interpreter::new().with_file_system(files.clone());
+        let evaluate = |code| interpreter.evaluate(&parse_expr(code));
+
+        assert_eq!(
+            evaluate("read_file(\"greeting.txt\")").unwrap(),
+            LoxResult::Str(String::from("hello"))
+        );
+        assert_eq!(
+            evaluate("write_file(\"out.txt\", read_file(\"greeting.txt\") + \"!\")").unwrap(),
+            LoxResult::Nil
+        );
+        assert_eq!(files.read("out.txt").unwrap(), "hello!");
+
+        for (code, message) in [
+            (
+                "read_file(\"missing.txt\")",
+                "Can't read missing.txt: No such file",
+            ),
+            (
+                "read_file(1)",
+                "read_file expects a string path but got number",
+            ),
+            (
+                "write_file(\"out.txt\", 1)",
+                "write_file expects two strings but got string and number",
+            ),
+        ] {
+            let err = evaluate(code).unwrap_err();
+            assert_eq!((err.code(), err.message.as_str()), ("E107", message));
+        }
+    }
+
+    #[test]
+    fn statement_coverage() {
+        let interpreter = Interpreter::new()
+            .with_output(Capture::default())
+            .with_coverage();
+        let code =
+            "var a = 1;\nif (a == 1) print \"one\"; else { print \"other\"; }\nfun f() { return; }";
+        let stmts = parse_code(code);
+        interpreter.interpret(&stmts).unwrap();
+
+        let coverage = interpreter.coverage(&stmts).unwrap();
+        assert_eq!(coverage.statements, 6);
+        let uncovered: Vec<_> = coverage
+            .uncovered
+            .iter()
+            .map(|&(index, len)| &code[index..index + len])
+            .collect();
+        assert_eq!(uncovered, ["\"other\"", "return"]);
+
+        // Each call starts over, only the body of `f` ran since
+        run("f();", &interpreter);
+        assert_eq!(interpreter.coverage(&stmts).unwrap().uncovered.len(), 5);
+        assert_eq!(Interpreter::new().coverage(&stmts), None);
+    }
+
+    /// Writer and reader recording in order when they are written to, flushed and read from
+    #[derive(Clone, Default)]
+    struct Events(Rc<RefCell<Vec<String>>>);
+
+    impl Write for Events {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let text = String::from_utf8_lossy(buf);
+            self.0.borrow_mut().push(format!("write {:?}", text));
+            Ok(buf.len())
+        }
 
-                res
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.borrow_mut().push(String::from("flush"));
+            Ok(())
+        }
+    }
+
+    impl std::io::Read for Events {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().push(String::from("read"));
+            let line = b"lox\n";
+            buf[..line.len()].copy_from_slice(line);
+            Ok(line.len())
+        }
+    }
+
+    #[test]
+    fn prompts_are_flushed_before_reading() {
+        let events = Events::default();
+        let interpreter = Interpreter::new()
+            .with_output(events.clone())
+            .with_input(std::io::BufReader::new(events.clone()));
+        run(
+            "write(\"? \"); var name = read_line(); print name;",
+            &interpreter,
+        );
+        assert_eq!(
+            *events.0.borrow(),
+            [
+                "write \"? \"",
+                "flush",
+                "flush",
+                "read",
+                "write \"lox\"",
+                "write \"\\n\"",
+            ]
+        );
+
+        let interpreter = Interpreter::new().with_input(std::io::Cursor::new("a\r\nb"));
+        let evaluate = |code| interpreter.evaluate(&parse_expr(code)).unwrap();
+        assert_eq!(evaluate("read_line()"), LoxResult::Str(String::from("a")));
+        assert_eq!(evaluate("read_line()"), LoxResult::Str(String::from("b")));
+        assert_eq!(evaluate("read_line()"), LoxResult::Nil);
+    }
+
+    #[test]
+    fn user_functions() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run(
+            "fun add(a, b) { return a + b; } print add(1, 2); print add;",
+            &interpreter,
+        );
+        run(
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } print fib(15);",
+            &interpreter,
+        );
+        // A bare return stops the body and yields nil, as does reaching its end
+        run(
+            "fun early() { print 1; return; print 2; } print early();",
+            &interpreter,
+        );
+        run("fun nothing() {} print nothing();", &interpreter);
+        // Bodies see the scope the function was declared in
+        run(
+            "var x = 1; { var y = 2; fun f(z) { return x + y + z; } print f(3); }",
+            &interpreter,
+        );
+        // Attributes the interpreter doesn't know about are ignored
+        run(
+            "@memoize fun one() { return 1; } print one();",
+            &interpreter,
+        );
+        assert_eq!(output.contents(), "3\n<fn add>\n610\n1\nnil\nnil\n6\n1\n");
+
+        let stmts = parse_code("add(1);");
+        let err = interpreter.interpret(&stmts).unwrap_err();
+        assert_eq!(err.message, "Expected 2 arguments but got 1");
+        assert_eq!((err.index, err.len), (0, 6));
+        // Parameters only live in the call frame
+        assert_eq!(interpreter.globals.borrow().get("a"), None);
+    }
+
+    #[test]
+    fn calls_nested_too_deep() {
+        let interpreter = Interpreter::new().with_config(Config {
+            max_call_depth: 10,
+            ..Config::default()
+        });
+        run(
+            "fun depth(n) { if (n == 1) return n; return depth(n - 1); } depth(10);",
+            &interpreter,
+        );
+
+        let stmts = parse_code("depth(11);");
+        let err = interpreter.interpret(&stmts).unwrap_err();
+        assert_eq!(err.code(), "E108");
+        assert_eq!(err.message, "Too many nested calls, more than 10");
+        assert_eq!((err.index, err.len), (44, 12));
+        // Calls that failed are no longer counted
+        run("depth(10);", &interpreter);
+    }
+
+    #[test]
+    fn interrupted_loops_stop() {
+        let output = Capture::default();
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let interpreter = Interpreter::new()
+            .with_output(output.clone())
+            .with_interrupt(interrupt.clone());
+
+        interrupt.store(true, atomic::Ordering::SeqCst);
+        let stmts = parse_code("var i = 0; while (true) i = i + 1;");
+        let err = interpreter.interpret(&stmts).unwrap_err();
+        assert_eq!(err.code(), "E109");
+        assert_eq!((err.index, err.len), (18, 4));
+        // The interrupt only stops the program that was running
+        assert!(!interrupt.load(atomic::Ordering::SeqCst));
+        run("print i;", &interpreter);
+        assert_eq!(output.contents(), "0\n");
+    }
+
+    #[test]
+    fn return_from_loops() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run(
+            "fun find(limit) {
+                for (var i = 0; i < 10; i = i + 1) {
+                    print i;
+                    if (i == limit) return i;
+                }
+                return -1;
             }
+            print find(2);",
+            &interpreter,
+        );
+        // Nothing after the return runs, however deep it is
+        run(
+            "fun stop() { while (true) { { return \"done\"; } print \"never\"; } }
+            print stop();",
+            &interpreter,
+        );
+        assert_eq!(output.contents(), "0\n1\n2\n2\ndone\n");
+    }
+
+    #[test]
+    fn loop_scopes_per_iteration() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        // Each closure keeps the variables of the iteration that declared it
+        run(
+            "var first; var second;
+            for (var i = 0; i < 3; i = i + 1) {
+                var count = i * 10;
+                fun counter() { count = count + 1; return count; }
+                if (i == 0) first = counter;
+                if (i == 1) second = counter;
+            }
+            print first(); print first(); print second();",
+            &interpreter,
+        );
+        // Variables of the previous iteration are gone, even when the scope is reused
+        run(
+            "var x = \"outer\"; var i = 0;
+            while (i < 2) { { print x; var x = i; } i = i + 1; }",
+            &interpreter,
+        );
+        assert_eq!(output.contents(), "1\n2\n11\nouter\nouter\n");
+    }
+
+    #[test]
+    fn comma_evaluation_order() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run("var a; print (a = 1, a + 1);", &interpreter);
+        // Each operand runs exactly once, left first
+        run("var b = (print(\"left\"), print(\"right\"));", &interpreter);
+        run("print b;", &interpreter);
+        assert_eq!(output.contents(), "2\nleft\nright\nright\n");
+    }
+
+    #[test]
+    fn long_operator_chains() {
+        let interpreter = Interpreter::new();
+        let recursing = Interpreter::new().with_config(Config {
+            long_chain: None,
+            ..Config::default()
+        });
+        let evaluate = |code: &str| {
+            let expr = parse_expr(code);
+            let iterative = eval_operators(&expr, &interpreter, interpreter.globals.clone())
+                .map_err(|e| (e.message.clone(), e.index, e.len));
+            let recursive = recursing
+                .evaluate(&expr)
+                .map_err(|e| (e.message, e.index, e.len));
+            assert_eq!(iterative, recursive, "{}", code);
+            iterative
         };
-        Ok(res)
+
+        let chain: String = (1..=200)
+            .map(|n| format!("{} {}", if n % 2 == 0 { "-" } else { "+" }, n))
+            .collect();
+        assert_eq!(
+            evaluate(&format!("0 {}", chain)),
+            Ok(LoxResult::Number(-100.0))
+        );
+        for code in [
+            "-(1 + 2) * 3 - !true",
+            "\"a\" + 1 + (2, 3)",
+            "1 + 2 * -\"a\"",
+            "1 + 2 / (1 - 1)",
+        ] {
+            // Only checks that both evaluations agree, errors included
+            let _ = evaluate(code);
+        }
+        let code = format!("1{} + nil", " + 1".repeat(100));
+        assert_eq!(
+            evaluate(&code),
+            Err((
+                String::from("Cant operate on Number and Nil"),
+                0,
+                code.len()
+            ))
+        );
+
+        // Operands still run left to right, each exactly once
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        let calls: Vec<String> = (0..100).map(|n| format!("print({})", n)).collect();
+        run(&format!("var last = {};", calls.join(", ")), &interpreter);
+        let printed: Vec<String> = (0..100).map(|n| format!("{}\n", n)).collect();
+        assert_eq!(output.contents(), printed.concat());
+        assert_eq!(
+            interpreter.globals.borrow().get("last"),
+            Some(Some(LoxResult::Number(99.0)))
+        );
+    }
+
+    #[test]
+    fn print_native_returns_its_argument() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run("var x = print(1 + 2) * 2; print x;", &interpreter);
+        run("print(\"a\"); print print(nil);", &interpreter);
+        assert_eq!(output.contents(), "3\n6\na\nnil\nnil\n");
+    }
+
+    #[test]
+    fn logical_short_circuit() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run("print nil or 2;", &interpreter);
+        run("print 1 and \"a\";", &interpreter);
+        run("print false and 1;", &interpreter);
+        run("print 0 or 1;", &interpreter);
+        // The value deciding is the result, not a boolean
+        run("print nil or \"x\";", &interpreter);
+        run("print 0 and 2;", &interpreter);
+        // The right operand isn't evaluated once the left one decides
+        run(
+            "var x = 0; true or (x = 1); false and (x = 2); print x;",
+            &interpreter,
+        );
+        run("nil and (x = 3); nil or (x = 4); print x;", &interpreter);
+        assert_eq!(output.contents(), "2\na\nfalse\n0\nx\n2\n0\n4\n");
+    }
+
+    #[test]
+    fn echo_expression_values() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run("print 1; 1 + 1;", &interpreter);
+        assert_eq!(output.contents(), "1\n");
+
+        let output = Capture::default();
+        let interpreter = Interpreter::new()
+            .with_output(output.clone())
+            .with_config(Config {
+                echo_expression_values: true,
+                ..Config::default()
+            });
+        run("print 1; 1 + 1; { 3; }", &interpreter);
+        assert_eq!(output.contents(), "1\n2\n");
+    }
+
+    #[test]
+    fn scopes_holding_functions_are_freed() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run("fun outer() { fun inner() {} return 1; }", &interpreter);
+        // Held by the interpreter and as the closure of `outer`
+        assert_eq!(Rc::strong_count(&interpreter.globals), 2);
+        run(
+            "outer(); { fun f() {} } var i = 0; while (i < 3) { fun g() { return g; } i = i + 1; }",
+            &interpreter,
+        );
+        // None of the scopes that were left holds on to it through its parent
+        assert_eq!(Rc::strong_count(&interpreter.globals), 2);
+
+        // Escaping functions keep the variables they captured
+        run(
+            "fun make() { var x = 1; fun get() { return x; } return get; } print make()();",
+            &interpreter,
+        );
+        assert_eq!(output.contents(), "1\n");
+
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let freed = Rc::downgrade(&globals);
+        let interpreter = Interpreter::new().with_globals(globals);
+        run("fun f() { return f; } f();", &interpreter);
+        drop(interpreter);
+        assert!(freed.upgrade().is_none());
+    }
+
+    #[test]
+    fn block_locals_do_not_leak() {
+        let interpreter = Interpreter::new();
+        run("var a = 1; { var a = 2; var b = 3; }", &interpreter);
+        assert_eq!(
+            interpreter.globals.borrow().get("a"),
+            Some(Some(LoxResult::Number(1.0)))
+        );
+        assert_eq!(interpreter.globals.borrow().get("b"), None);
+
+        // A runtime error half way through the block drops its scope all the same
+        let stmts = parse_code("{ var a = 4; var c = 5; c - \"c\"; var d = 6; }");
+        assert!(interpreter.interpret(&stmts).is_err());
+        let env = interpreter.globals.borrow();
+        assert_eq!(env.get("a"), Some(Some(LoxResult::Number(1.0))));
+        assert_eq!(env.get("c"), None);
+        assert_eq!(env.get("d"), None);
+    }
+
+    #[test]
+    fn quoted_repr() {
+        assert_eq!(LoxResult::Str(String::from("a\nb")).repr(), "\"a\\nb\"");
+        assert_eq!(
+            LoxResult::Str(String::from("say \"hi\"\t\\\u{7}")).repr(),
+            "\"say \\\"hi\\\"\\t\\\\\\u{7}\""
+        );
+        assert_eq!(LoxResult::Number(1.5).repr(), "1.5");
+        assert_eq!(LoxResult::Str(String::from("a\nb")).to_string(), "a\nb");
+        assert_eq!(
+            evaluate(r#"repr("a\nb")"#).unwrap(),
+            LoxResult::Str(String::from("\"a\\nb\""))
+        );
+        assert_eq!(
+            evaluate("repr(1.5)").unwrap(),
+            LoxResult::Str(String::from("1.5"))
+        );
+
+        let output = Capture::default();
+        let interpreter = Interpreter::new()
+            .with_output(output.clone())
+            .with_config(Config {
+                echo_expression_values: true,
+                ..Config::default()
+            });
+        run("\"echo\";", &interpreter);
+        assert_eq!(output.contents(), "\"echo\"\n");
+    }
+
+    #[test]
+    fn assert_not_nil() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run("var x = 1; print x! + 1;", &interpreter);
+        assert_eq!(output.contents(), "2\n");
+
+        let stmts = parse_code("var y; y!;");
+        let err = interpreter.interpret(&stmts).unwrap_err();
+        assert_eq!(err.message, "Unexpected nil");
+        assert_eq!((err.index, err.len), (7, 2));
+    }
+
+    #[test]
+    fn negation_edge_cases() {
+        match evaluate("-0.0").unwrap() {
+            LoxResult::Number(n) => assert!(n == 0.0 && n.is_sign_negative()),
+            r => panic!("Expected a number but got {:?}", r),
+        }
+        assert_eq!(evaluate("-0.0").unwrap().to_string(), "-0");
+        assert_eq!(evaluate("- -5").unwrap(), LoxResult::Number(5.0));
+        assert_eq!(evaluate("--5").unwrap(), LoxResult::Number(5.0));
+        assert_eq!(
+            evaluate("-9007199254740993").unwrap(),
+            LoxResult::Number(-9007199254740992.0)
+        );
+        assert_eq!(evaluate("-(1 - 3)").unwrap(), LoxResult::Number(2.0));
+    }
+
+    #[test]
+    fn interpret_cells_in_order() {
+        let interpreter = Interpreter::new().with_output(Capture::default());
+        let stmts = parse_code("var a = 1; a + 1; print a;");
+        assert_eq!(
+            interpreter.interpret_cells(&stmts).unwrap(),
+            vec![LoxResult::Nil, LoxResult::Number(2.0), LoxResult::Nil]
+        );
+    }
+
+    #[test]
+    fn nil_semantics() {
+        assert_eq!(evaluate("nil").unwrap(), LoxResult::Nil);
+        assert_eq!(evaluate("(nil)").unwrap(), LoxResult::Nil);
+        assert_eq!(evaluate("nil == nil").unwrap(), LoxResult::Bool(true));
+        assert_eq!(evaluate("nil != nil").unwrap(), LoxResult::Bool(false));
+        // Nil is falsy, but only equal to itself
+        assert_eq!(evaluate("!nil").unwrap(), LoxResult::Bool(true));
+        assert_eq!(evaluate("nil == false").unwrap(), LoxResult::Bool(false));
+        assert_eq!(evaluate("nil == 1").unwrap(), LoxResult::Bool(false));
+        assert_eq!(evaluate("true != nil").unwrap(), LoxResult::Bool(true));
+        assert_eq!(evaluate("1 == \"1\"").unwrap(), LoxResult::Bool(false));
+        assert!(evaluate("nil < 1").is_err());
+    }
+
+    #[test]
+    fn division_by_zero() {
+        for code in ["1 / 0", "0 / 0", "1 / -0", "2 * (1 / (1 - 1))"] {
+            let err = evaluate(code).unwrap_err();
+            assert_eq!(err.message, "Division by zero");
+        }
+        let err = evaluate("1 + 1 / 0").unwrap_err();
+        assert_eq!((err.index, err.len), (4, 5));
+        assert_eq!(evaluate("0 / 2").unwrap(), LoxResult::Number(0.0));
+    }
+
+    #[test]
+    fn concatenate_strings_with_other_values() {
+        let output = Capture::default();
+        let interpreter = Interpreter::new().with_output(output.clone());
+        run(
+            "print \"a\" + 1; print 1 + \"a\"; print 1 + 2; print \"count: \" + 1.5 + nil;",
+            &interpreter,
+        );
+        assert_eq!(output.contents(), "a1\n1a\n3\ncount: 1.5nil\n");
+    }
+
+    #[test]
+    fn ordering_comparisons() {
+        let compare = |code: &str| evaluate(code).map_err(|e| e.message);
+
+        assert_eq!(compare("\"a\" < \"b\""), Ok(LoxResult::Bool(true)));
+        assert_eq!(compare("\"ab\" >= \"b\""), Ok(LoxResult::Bool(false)));
+        assert_eq!(compare("2 <= 10"), Ok(LoxResult::Bool(true)));
+        assert_eq!(
+            compare("1 < \"a\""),
+            Err(String::from("Can't compare Number and Str"))
+        );
+        assert_eq!(
+            compare("clock > clock"),
+            Err(String::from("Can't compare Function and Function"))
+        );
+    }
+
+    #[test]
+    fn coercion_mode() {
+        let coercing = Interpreter::new().with_config(Config {
+            coerce: true,
+            ..Config::default()
+        });
+        let strict = Interpreter::new();
+        let result = |interpreter: &Interpreter, code| {
+            interpreter
+                .evaluate(&parse_expr(code))
+                .map(|r| r.to_string())
+                .map_err(|e| e.message)
+        };
+
+        for (code, coerced, strict_result) in [
+            ("\"3\" + 4", Ok("7"), Ok("34")),
+            (
+                "4 - \" 1.5 \"",
+                Ok("2.5"),
+                Err("Cant operate on Number and Str"),
+            ),
+            (
+                "\"6\" / \"2\"",
+                Ok("3"),
+                Err("Can't perform Division on Str"),
+            ),
+            // Strings are still concatenated with each other
+            ("\"3\" + \"4\"", Ok("34"), Ok("34")),
+            (
+                "\"three\" * 2",
+                Err("Can't coerce \"three\" to a number"),
+                Err("Cant operate on Str and Number"),
+            ),
+            (
+                "\"1\" / \"0\"",
+                Err("Division by zero"),
+                Err("Can't perform Division on Str"),
+            ),
+            // Only arithmetic coerces
+            ("\"3\" == 3", Ok("false"), Ok("false")),
+            (
+                "true + 1",
+                Err("Cant operate on Bool and Number"),
+                Err("Cant operate on Bool and Number"),
+            ),
+        ] {
+            let expected = |r: Result<&str, &str>| r.map(String::from).map_err(String::from);
+            assert_eq!(result(&coercing, code), expected(coerced), "{}", code);
+            assert_eq!(result(&strict, code), expected(strict_result), "{}", code);
+        }
+    }
+
+    #[test]
+    fn binary_operation_table() {
+        let result = |code: &str| evaluate(code).map_err(|e| e.message).map(|r| r.to_string());
+        let operands = ["2", "\"a\"", "true", "nil"];
+        let operators = ["+", "-", "*", "/", "==", "!=", ">", ">=", "<", "<="];
+        let type_names = ["Number", "Str", "Bool", "Nil"];
+        let displays = ["2", "a", "true", "nil"];
+
+        let mut results = Vec::new();
+        for (l, left) in operands.iter().enumerate() {
+            for (r, right) in operands.iter().enumerate() {
+                for operator in operators.iter() {
+                    let result = result(&format!("{} {} {}", left, operator, right));
+                    if l != r && *operator == "+" && (l == 1 || r == 1) {
+                        assert_eq!(result, Ok(format!("{}{}", displays[l], displays[r])));
+                    } else if l != r && (*operator == "==" || *operator == "!=") {
+                        assert_eq!(result, Ok((*operator == "!=").to_string()));
+                    } else if l != r && ["<", "<=", ">", ">="].contains(operator) {
+                        assert_eq!(
+                            result,
+                            Err(format!(
+                                "Can't compare {} and {}",
+                                type_names[l], type_names[r]
+                            ))
+                        );
+                    } else if l != r {
+                        assert_eq!(
+                            result,
+                            Err(format!(
+                                "Cant operate on {} and {}",
+                                type_names[l], type_names[r]
+                            ))
+                        );
+                    } else {
+                        results.push(format!("{} {} {} = {:?}", left, operator, right, result));
+                    }
+                }
+                assert_eq!(result(&format!("({}, {})", left, right)), result(right));
+            }
+        }
+
+        let expected = vec![
+            "2 + 2 = Ok(\"4\")",
+            "2 - 2 = Ok(\"0\")",
+            "2 * 2 = Ok(\"4\")",
+            "2 / 2 = Ok(\"1\")",
+            "2 == 2 = Ok(\"true\")",
+            "2 != 2 = Ok(\"false\")",
+            "2 > 2 = Ok(\"false\")",
+            "2 >= 2 = Ok(\"true\")",
+            "2 < 2 = Ok(\"false\")",
+            "2 <= 2 = Ok(\"true\")",
+            "\"a\" + \"a\" = Ok(\"aa\")",
+            "\"a\" - \"a\" = Err(\"Can't perform Substraction on Str\")",
+            "\"a\" * \"a\" = Err(\"Can't perform Product on Str\")",
+            "\"a\" / \"a\" = Err(\"Can't perform Division on Str\")",
+            "\"a\" == \"a\" = Ok(\"true\")",
+            "\"a\" != \"a\" = Ok(\"false\")",
+            "\"a\" > \"a\" = Ok(\"false\")",
+            "\"a\" >= \"a\" = Ok(\"true\")",
+            "\"a\" < \"a\" = Ok(\"false\")",
+            "\"a\" <= \"a\" = Ok(\"true\")",
+            "true + true = Err(\"Can't perform Sum on Bool\")",
+            "true - true = Err(\"Can't perform Substraction on Bool\")",
+            "true * true = Err(\"Can't perform Product on Bool\")",
+            "true / true = Err(\"Can't perform Division on Bool\")",
+            "true == true = Ok(\"true\")",
+            "true != true = Ok(\"false\")",
+            "true > true = Err(\"Can't compare Bool and Bool\")",
+            "true >= true = Err(\"Can't compare Bool and Bool\")",
+            "true < true = Err(\"Can't compare Bool and Bool\")",
+            "true <= true = Err(\"Can't compare Bool and Bool\")",
+            "nil + nil = Err(\"Can't perform Sum on Nil\")",
+            "nil - nil = Err(\"Can't perform Substraction on Nil\")",
+            "nil * nil = Err(\"Can't perform Product on Nil\")",
+            "nil / nil = Err(\"Can't perform Division on Nil\")",
+            "nil == nil = Ok(\"true\")",
+            "nil != nil = Ok(\"false\")",
+            "nil > nil = Err(\"Can't compare Nil and Nil\")",
+            "nil >= nil = Err(\"Can't compare Nil and Nil\")",
+            "nil < nil = Err(\"Can't compare Nil and Nil\")",
+            "nil <= nil = Err(\"Can't compare Nil and Nil\")",
+        ];
+        assert_eq!(results, expected);
+    }
+
+    #[test]
+    fn define_many_natives() {
+        let globals = Environment::new();
+        for native in natives::NATIVES.iter() {
+            assert_eq!(
+                globals.get(native.name()),
+                Some(Some(LoxResult::NativeFn(native.clone())))
+            );
+        }
+
+        let mut scope = Environment::with_capacity(0, None);
+        scope.define_many(vec![
+            (String::from("a"), LoxResult::Number(1.0)),
+            (String::from("b"), LoxResult::Nil),
+        ]);
+        assert!(scope.scope.capacity() >= 2);
+        assert_eq!(scope.get("a"), Some(Some(LoxResult::Number(1.0))));
+        assert_eq!(scope.get("b"), Some(Some(LoxResult::Nil)));
+    }
+
+    #[test]
+    fn locate_scope_depth() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals.borrow_mut().declare(String::from("a"), None);
+        globals.borrow_mut().declare(String::from("b"), None);
+        let mut block = Environment::with_capacity(1, Some(globals));
+        block.declare(String::from("a"), None);
+        let mut local = Environment::with_capacity(1, Some(Rc::new(RefCell::new(block))));
+
+        assert_eq!(local.locate("a"), Some(1));
+        assert_eq!(local.locate("b"), Some(2));
+        assert_eq!(local.locate("c"), None);
+
+        local.declare(String::from("b"), None);
+        assert_eq!(local.locate("b"), Some(0));
+    }
+
+    #[test]
+    fn lox_result_conversions() {
+        assert_eq!(LoxResult::from(1.5), LoxResult::Number(1.5));
+        assert_eq!(LoxResult::from(-3i64), LoxResult::Number(-3.0));
+        assert_eq!(LoxResult::from(true), LoxResult::Bool(true));
+        assert_eq!(
+            LoxResult::from(String::from("a")),
+            LoxResult::Str(String::from("a"))
+        );
+        assert_eq!(LoxResult::from("b"), LoxResult::Str(String::from("b")));
+        assert_eq!(LoxResult::from(()), LoxResult::Nil);
+
+        let n: f64 = LoxResult::Number(2.0).try_into().unwrap();
+        assert_eq!(n, 2.0);
+        let s: String = LoxResult::from("c").try_into().unwrap();
+        assert_eq!(s, "c");
+
+        let err = f64::try_from(LoxResult::from("1")).unwrap_err();
+        assert_eq!(err.message, "Expected a number but got a string");
+        let err = String::try_from(LoxResult::Nil).unwrap_err();
+        assert_eq!(err.message, "Expected a string but got a nil");
+    }
+
+    #[test]
+    fn runtime_error_codes() {
+        let interpreter = Interpreter::new();
+        let code_of = |code: &str| interpreter.interpret(&parse_code(code)).unwrap_err().code();
+
+        for (program, code) in [
+            ("1 - true;", "E101"),
+            ("print missing;", "E102"),
+            ("1 / 0;", "E103"),
+            ("nil!;", "E104"),
+            ("\"not a function\"();", "E105"),
+            ("fun f(a) {} f();", "E106"),
+            ("fun f() { f(); } f();", "E108"),
+        ] {
+            assert_eq!(code_of(program), code, "{}", program);
+            assert!(crate::diagnostics::explain(code).is_some());
+        }
+
+        // Undefined variables point at just the name
+        let stmts = parse_code("print 1 + missing;");
+        assert_eq!(interpreter.interpret(&stmts).unwrap_err().span(), (10, 7));
     }
 }
@@ -1,27 +1,77 @@
-use std::{convert::TryFrom, iter::Peekable};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead},
+    iter::FromIterator,
+    iter::Peekable,
+    sync::OnceLock,
+};
 
 use self::KeywordKind::*;
 use self::LiteralKind::*;
 use self::TokenKind::*;
 
-pub fn tokenize(mut code: &str) -> impl Iterator<Item = Token> + Clone + '_ {
+/// Splits `code` into tokens with the English keywords. Whitespace and comments are tokens too,
+/// and nothing is ever skipped: characters that can't start a token become `Unknown` ones.
+pub fn tokenize(code: &str) -> impl Iterator<Item = Token> + Clone + '_ {
+    static ENGLISH: OnceLock<Keywords> = OnceLock::new();
+    tokenize_with_keywords(code, ENGLISH.get_or_init(Keywords::default))
+}
+
+/// Like `tokenize` but ends with an empty `Eof` token positioned at the end of the input, so errors
+/// about missing tokens can point there
+pub fn tokenize_with_eof(code: &str) -> impl Iterator<Item = Token> + Clone + '_ {
+    let (line, column) = advance(code, 1, 1);
+    tokenize(code).chain(std::iter::once(Token::new(
+        Eof,
+        code.len(),
+        0,
+        line,
+        column,
+    )))
+}
+
+/// Tokenizes `code` recognizing the words in `keywords` as the language keywords
+pub fn tokenize_with_keywords<'a>(
+    mut code: &'a str,
+    keywords: &'a Keywords,
+) -> impl Iterator<Item = Token> + Clone + 'a {
     let mut index = 0;
+    let (mut line, mut column) = (1, 1);
     std::iter::from_fn(move || {
-        let token = next_token(code, index);
-        index += token.len;
-        if token.kind == Eof {
-            None
+        let (kind, len) = next_token(code, keywords);
+        if kind == Eof {
+            return None;
+        }
+        let token = Token::new(kind, index, len, line, column);
+        (line, column) = advance(&code[..len], line, column);
+        index += len;
+        code = &code[len..];
+        Some(token)
+    })
+}
+
+/// 1-based line and column of the byte `index` of `code`. Columns count characters, not bytes.
+pub fn line_column(code: &str, index: usize) -> (usize, usize) {
+    advance(&code[..index.min(code.len())], 1, 1)
+}
+
+/// Moves the `line` and `column` position past `text`
+fn advance(text: &str, line: usize, column: usize) -> (usize, usize) {
+    text.chars().fold((line, column), |(line, column), c| {
+        if c == '\n' {
+            (line + 1, 1)
         } else {
-            code = &code[token.len..];
-            Some(token)
+            (line, column + 1)
         }
     })
 }
 
-fn next_token(code: &str, index: usize) -> Token {
+/// Kind and length in bytes of the token at the start of `code`
+fn next_token(code: &str, keywords: &Keywords) -> (TokenKind, usize) {
     let mut chars = code.chars().peekable();
-    let mut consumed = 1;
-    let token_kind = match chars.next() {
+    let first = chars.next();
+    let mut consumed = first.map_or(0, char::len_utf8);
+    let token_kind = match first {
         Some('(') => LeftParen,
         Some(')') => RightParen,
         Some('{') => LeftBrace,
@@ -33,6 +83,7 @@ fn next_token(code: &str, index: usize) -> Token {
         Some(';') => Semicolon,
         Some(':') => Colon,
         Some('?') => Interrogation,
+        Some('@') => At,
         Some('/') => {
             if let Some('/') = chars.peek() {
                 let (c, _, _) = consume_while(&mut chars, |c| c != '\u{000A}');
@@ -44,14 +95,13 @@ fn next_token(code: &str, index: usize) -> Token {
         }
         Some('*') => Star,
         Some('"') => {
-            let (c, terminated, value) = consume_while(&mut chars, |c| c != '"');
+            let (c, terminated, value, valid_escapes) = consume_string(&mut chars);
             consumed += c;
-            // Consume while does not consume the ending character '"', so we have do do it here
-            if terminated {
-                chars.next();
-                consumed += 1;
+            if valid_escapes {
+                Literal(Str { terminated, value })
+            } else {
+                Literal(MalformedString(code[..consumed].into()))
             }
-            Literal(Str { terminated, value })
         }
         Some('!') => {
             if let Some('=') = chars.peek() {
@@ -101,6 +151,31 @@ fn next_token(code: &str, index: usize) -> Token {
                 GreaterThan
             }
         }
+        Some('0') if matches!(chars.peek(), Some('x') | Some('X')) => {
+            chars.next();
+            let (c, value) = consume_hex_number(&mut chars);
+            consumed += c + 1;
+            let is_int = consume_int_suffix(&mut chars, &mut consumed);
+            let whole = !value.contains(['.', 'p', 'P']);
+            match parse_hex_number(&value) {
+                Some(value) if whole || !is_int => Literal(Number { value, is_int }),
+                _ => Literal(MalformedNumber(code[..consumed].into())),
+            }
+        }
+        Some('0') if matches!(chars.peek(), Some('b') | Some('B')) => {
+            chars.next();
+            // Every decimal digit is taken so `0b102` is malformed instead of two numbers
+            let (c, _, digits) = consume_while(&mut chars, is_digit);
+            consumed += c + 1;
+            let is_int = consume_int_suffix(&mut chars, &mut consumed);
+            match u64::from_str_radix(&digits, 2) {
+                Ok(value) => Literal(Number {
+                    value: value as f64,
+                    is_int,
+                }),
+                Err(_) => Literal(MalformedNumber(code[..consumed].into())),
+            }
+        }
         Some(c) if is_digit(c) => {
             let (s, _, value) = consume_while(&mut chars, is_digit);
             let mut str_number = String::with_capacity(s + 1);
@@ -108,6 +183,7 @@ fn next_token(code: &str, index: usize) -> Token {
             str_number.push_str(&value);
             consumed += s;
             let mut foreview = chars.clone();
+            let mut fractional = false;
             if let Some(c) = chars.peek() {
                 if *c == '.' {
                     foreview.next();
@@ -118,11 +194,40 @@ fn next_token(code: &str, index: usize) -> Token {
                             str_number.push('.');
                             str_number.push_str(&value);
                             consumed += c + 1;
+                            fractional = true;
                         }
                     }
                 }
             }
-            Literal(Number(str_number.parse().unwrap()))
+            // The exponent is only taken when digits follow the `e` and its optional sign
+            let mut exponent = false;
+            let mut foreview = chars.clone();
+            if let Some(e @ ('e' | 'E')) = foreview.next() {
+                let sign = foreview.next_if(|c| *c == '+' || *c == '-');
+                if foreview.peek().copied().is_some_and(is_digit) {
+                    chars.next();
+                    str_number.push(e);
+                    consumed += 1;
+                    if let Some(sign) = sign {
+                        chars.next();
+                        str_number.push(sign);
+                        consumed += 1;
+                    }
+                    let (c, _, value) = consume_while(&mut chars, is_digit);
+                    str_number.push_str(&value);
+                    consumed += c;
+                    exponent = true;
+                }
+            }
+            let is_int = consume_int_suffix(&mut chars, &mut consumed);
+            if is_int && (fractional || exponent) {
+                Literal(MalformedNumber(code[..consumed].into()))
+            } else {
+                Literal(Number {
+                    value: str_number.parse().unwrap(),
+                    is_int,
+                })
+            }
         }
         Some(c) if is_whitespace(c) => {
             let (c, _, _) = consume_while(&mut chars, is_whitespace);
@@ -133,17 +238,17 @@ fn next_token(code: &str, index: usize) -> Token {
             let (c, _, _) = consume_while(&mut chars, is_ident_continue);
             consumed += c;
             let s = &code[..consumed];
-            if let Ok(k) = KeywordKind::try_from(s) {
-                Keyword(k)
+            if let Some(k) = keywords.get(s) {
+                Keyword(k.clone())
             } else {
                 Identifier(s.into())
             }
         }
-        Some(_) => Unknown,
+        Some(c) => Unknown(c),
         _ => Eof,
     };
 
-    Token::new(token_kind, index, consumed)
+    (token_kind, consumed)
 }
 
 fn consume_while(
@@ -157,7 +262,7 @@ fn consume_while(
         if f(*c) {
             let c = chars.next().unwrap();
             value.push(c);
-            consumed += 1;
+            consumed += c.len_utf8();
         } else {
             terminated = true;
             break;
@@ -166,8 +271,142 @@ fn consume_while(
     (consumed, terminated, value)
 }
 
+/// Consumes the body of a string after its opening `"`, and the closing one if there is one.
+/// Returns the bytes consumed, whether the string was closed, its value with the escape sequences
+/// decoded and whether all of them were valid. Invalid escape sequences are left out of the value.
+fn consume_string(chars: &mut Peekable<impl Iterator<Item = char>>) -> (usize, bool, String, bool) {
+    let mut consumed = 0;
+    let mut value = String::with_capacity(8);
+    let mut valid_escapes = true;
+    while let Some(c) = chars.next() {
+        consumed += c.len_utf8();
+        let decoded = match c {
+            '"' => return (consumed, true, value, valid_escapes),
+            '\\' => match chars.next() {
+                Some('u') => {
+                    let (c, decoded) = consume_unicode_escape(chars);
+                    consumed += 1 + c;
+                    decoded
+                }
+                Some(c) => {
+                    consumed += c.len_utf8();
+                    escape(c)
+                }
+                None => break,
+            },
+            c => Some(c),
+        };
+        match decoded {
+            Some(c) => value.push(c),
+            None => valid_escapes = false,
+        }
+    }
+    (consumed, false, value, valid_escapes)
+}
+
+/// Consumes the `{1F600}` following `\u` in a string, up to six hex digits naming a Unicode
+/// scalar value. Returns the bytes consumed and the character, if the escape was valid.
+fn consume_unicode_escape(
+    chars: &mut Peekable<impl Iterator<Item = char>>,
+) -> (usize, Option<char>) {
+    if chars.peek() != Some(&'{') {
+        return (0, None);
+    }
+    chars.next();
+    let (c, _, digits) = consume_while(chars, |c| c.is_ascii_hexdigit());
+    if chars.peek() != Some(&'}') {
+        return (1 + c, None);
+    }
+    chars.next();
+    let decoded = match digits.len() {
+        1..=6 => u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32),
+        _ => None,
+    };
+    (c + 2, decoded)
+}
+
+/// Character written as `\` followed by `c` inside strings
+fn escape(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '\\' => Some('\\'),
+        '"' => Some('"'),
+        '0' => Some('\0'),
+        _ => None,
+    }
+}
+
+/// Consumes the `n` that marks a number as meant to be an integer, if there is one
+fn consume_int_suffix(
+    chars: &mut Peekable<impl Iterator<Item = char>>,
+    consumed: &mut usize,
+) -> bool {
+    let is_int = chars.next_if_eq(&'n').is_some();
+    *consumed += is_int as usize;
+    is_int
+}
+
+/// Consumes the body of a hexadecimal number after its `0x` prefix: hex digits, an optional
+/// fraction and an optional binary exponent (`p` followed by a signed decimal number).
+/// Validation is left to `parse_hex_number`, so malformed bodies are consumed whole.
+fn consume_hex_number(chars: &mut Peekable<impl Iterator<Item = char>>) -> (usize, String) {
+    let mut consumed = 0;
+    let mut value = String::with_capacity(8);
+    let mut in_exponent = false;
+    while let Some(&c) = chars.peek() {
+        let accepted = if in_exponent {
+            is_digit(c) || ((c == '+' || c == '-') && value.ends_with(['p', 'P']))
+        } else {
+            c.is_ascii_hexdigit() || c == '.' || c == 'p' || c == 'P'
+        };
+        if !accepted {
+            break;
+        }
+        in_exponent = in_exponent || c == 'p' || c == 'P';
+        value.push(c);
+        chars.next();
+        consumed += 1;
+    }
+    (consumed, value)
+}
+
+/// Parses C99 style hexadecimal numbers such as `ff`, `1p4` or `1.8p3` (without the `0x` prefix).
+/// As in C, a fractional part is only valid together with a binary exponent.
+fn parse_hex_number(s: &str) -> Option<f64> {
+    let (mantissa, exponent) = match s.find(['p', 'P']) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let (integer, fraction) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], Some(&mantissa[i + 1..])),
+        None => (mantissa, None),
+    };
+    if fraction.is_some() && exponent.is_none() {
+        return None;
+    }
+    let fraction = fraction.unwrap_or("");
+    if integer.is_empty() && fraction.is_empty() {
+        return None;
+    }
+
+    let mut value = 0.0;
+    for digit in integer.chars().chain(fraction.chars()) {
+        value = value * 16.0 + f64::from(digit.to_digit(16)?);
+    }
+    let exponent: i32 = match exponent {
+        Some(e) => e.parse().ok()?,
+        None => 0,
+    };
+    Some(value * 2f64.powi(exponent - 4 * fraction.len() as i32))
+}
+
+/// True if `c` is a decimal digit
 pub fn is_digit(c: char) -> bool {
-    c >= '0' && c <= '9'
+    c.is_ascii_digit()
 }
 
 /// Shamefully stole this piece of code from the Rust language lexer implementation
@@ -209,22 +448,32 @@ pub fn is_ident_start(c: char) -> bool {
     c == '_' || unicode_xid::UnicodeXID::is_xid_start(c)
 }
 
+/// True if `c` is valid after the first character of an identifier.
 pub fn is_ident_continue(c: char) -> bool {
     unicode_xid::UnicodeXID::is_xid_continue(c)
 }
 
 /// It doesn't contain information about data that has been parsed,
 /// only the type of the token and its size.
+/// `index` and `len` are in bytes, `line` and `column` are 1-based and count characters.
 #[derive(Debug, Clone)]
 pub struct Token {
     pub kind: TokenKind,
     pub index: usize,
     pub len: usize,
+    pub line: usize,
+    pub column: usize,
 }
 
 impl Token {
-    fn new(kind: TokenKind, index: usize, len: usize) -> Token {
-        Token { kind, index, len }
+    fn new(kind: TokenKind, index: usize, len: usize, line: usize, column: usize) -> Token {
+        Token {
+            kind,
+            index,
+            len,
+            line,
+            column,
+        }
     }
 }
 
@@ -240,6 +489,7 @@ pub enum TokenKind {
     Semicolon,
     Colon,
     Interrogation,
+    At,
 
     Minus,
     Plus,
@@ -265,7 +515,8 @@ pub enum TokenKind {
 
     // Other
     Whitespace,
-    Unknown,
+    /// A character that can't start any token
+    Unknown(char),
     Eof,
 }
 
@@ -290,34 +541,644 @@ pub enum KeywordKind {
     While,
 }
 
-impl TryFrom<&str> for KeywordKind {
-    type Error = ();
-
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
-            "and" => Ok(And),
-            "class" => Ok(Class),
-            "else" => Ok(Else),
-            "false" => Ok(False),
-            "for" => Ok(For),
-            "fun" => Ok(Fun),
-            "if" => Ok(If),
-            "nil" => Ok(Nil),
-            "or" => Ok(Or),
-            "print" => Ok(Print),
-            "return" => Ok(Return),
-            "super" => Ok(Super),
-            "this" => Ok(This),
-            "true" => Ok(True),
-            "var" => Ok(Var),
-            "while" => Ok(While),
-            _ => Err(()),
-        }
+/// Table of the words the lexer treats as keywords. The default is the English keyword set,
+/// any other language can be used by collecting its own `(word, keyword)` pairs.
+#[derive(Debug, Clone)]
+pub struct Keywords(HashMap<String, KeywordKind>);
+
+impl Keywords {
+    pub fn get(&self, word: &str) -> Option<&KeywordKind> {
+        self.0.get(word)
+    }
+}
+
+impl Default for Keywords {
+    fn default() -> Self {
+        vec![
+            ("and", And),
+            ("class", Class),
+            ("else", Else),
+            ("false", False),
+            ("for", For),
+            ("fun", Fun),
+            ("if", If),
+            ("nil", Nil),
+            ("or", Or),
+            ("print", Print),
+            ("return", Return),
+            ("super", Super),
+            ("this", This),
+            ("true", True),
+            ("var", Var),
+            ("while", While),
+        ]
+        .into_iter()
+        .map(|(word, keyword)| (String::from(word), keyword))
+        .collect()
+    }
+}
+
+impl FromIterator<(String, KeywordKind)> for Keywords {
+    fn from_iter<I: IntoIterator<Item = (String, KeywordKind)>>(iter: I) -> Self {
+        Keywords(iter.into_iter().collect())
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum LiteralKind {
-    Str { terminated: bool, value: String },
-    Number(f64),
+    Str {
+        terminated: bool,
+        value: String,
+    },
+    /// Every number is a float, `is_int` records whether it was written with the `n` suffix
+    Number {
+        value: f64,
+        is_int: bool,
+    },
+    /// A number that could not be parsed, holding its source text
+    MalformedNumber(String),
+    /// A string with an unknown or malformed escape sequence, holding its source text
+    MalformedString(String),
+}
+
+/// JSON array with every token of `code`, whitespace and comments included, as objects with the
+/// kind of the token, its byte span and its source text. Literals are tagged with the kind of
+/// literal instead of `Literal`.
+pub fn tokens_json(code: &str) -> String {
+    let tokens: Vec<String> = tokenize(code)
+        .map(|t| {
+            let kind = match &t.kind {
+                Literal(literal) => format!("{:?}", literal),
+                kind => format!("{:?}", kind),
+            };
+            let tag = kind.split(['(', ' ']).next().unwrap_or_default();
+            format!(
+                "{{\"kind\": \"{}\", \"index\": {}, \"len\": {}, \"lexeme\": {}}}",
+                tag,
+                t.index,
+                t.len,
+                json_string(&code[t.index..t.index + t.len])
+            )
+        })
+        .collect();
+    format!("[{}]", tokens.join(", "))
+}
+
+/// Quotes `s` as a JSON string
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Source read a piece at a time, each piece holding whole top level statements, so big programs
+/// can be lexed, parsed and run without keeping all of their source in memory. Pieces end at
+/// the end of a line, after a `;` or `}` closing every parenthesis and brace opened before.
+pub struct Chunks<R> {
+    reader: R,
+    /// Line read ahead to find out whether it continues the statement before it, like an `else`
+    next_line: Option<String>,
+    /// Line number of the first line of the next chunk
+    line: usize,
+}
+
+/// A piece of source and the 1-based number of the line it starts at
+#[derive(Debug, PartialEq)]
+pub struct Chunk {
+    pub code: String,
+    pub line: usize,
+}
+
+impl<R: BufRead> Chunks<R> {
+    pub fn new(reader: R) -> Chunks<R> {
+        Chunks {
+            reader,
+            next_line: None,
+            line: 1,
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        if let Some(line) = self.next_line.take() {
+            return Ok(Some(line));
+        }
+        let mut line = String::new();
+        match self.reader.read_line(&mut line)? {
+            0 => Ok(None),
+            _ => Ok(Some(line)),
+        }
+    }
+
+    /// Reads the lines that follow a complete statement while they hold nothing but whitespace
+    /// and comments. Returns whether the first line with tokens starts with `else`, leaving that
+    /// line to be read next.
+    fn else_follows(&mut self, code: &mut String, lines: &mut usize) -> io::Result<bool> {
+        while let Some(line) = self.read_line()? {
+            let first = tokenize(&line).find(|t| !matches!(t.kind, Whitespace | Comment));
+            match first {
+                None => {
+                    code.push_str(&line);
+                    *lines += 1;
+                }
+                Some(t) => {
+                    self.next_line = Some(line);
+                    return Ok(t.kind == Keyword(Else));
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<R: BufRead> Iterator for Chunks<R> {
+    type Item = io::Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut code = String::new();
+        let mut lines = 0;
+        // Only the lines added since the last one are lexed, unless a string spans them
+        let mut lexed = 0;
+        let mut depth = 0;
+        loop {
+            let line = match self.read_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => return Some(Err(e)),
+            };
+            code.push_str(&line);
+            lines += 1;
+
+            let mut complete = false;
+            let mut lexed_until = code.len();
+            for t in tokenize(&code[lexed..]) {
+                match t.kind {
+                    Whitespace | Comment => continue,
+                    Literal(Str {
+                        terminated: false, ..
+                    }) => {
+                        lexed_until = lexed + t.index;
+                        complete = false;
+                        break;
+                    }
+                    LeftParen | LeftBrace => depth += 1,
+                    RightParen | RightBrace => depth -= 1,
+                    _ => {}
+                }
+                complete = depth <= 0 && matches!(t.kind, Semicolon | RightBrace);
+            }
+            lexed = lexed_until;
+
+            if complete {
+                match self.else_follows(&mut code, &mut lines) {
+                    Ok(true) => continue,
+                    Ok(false) => break,
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+        }
+        if code.is_empty() {
+            return None;
+        }
+        let chunk = Chunk {
+            code,
+            line: self.line,
+        };
+        self.line += lines;
+        Some(Ok(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        line_column, tokenize, tokenize_with_eof, tokenize_with_keywords, tokens_json, Chunks,
+        KeywordKind, Keywords, LiteralKind::*, TokenKind::*,
+    };
+
+    #[test]
+    fn emit_tokens_json() {
+        assert_eq!(
+            tokens_json("var s = \"a\tb\";\t// hi"),
+            concat!(
+                "[{\"kind\": \"Keyword\", \"index\": 0, \"len\": 3, \"lexeme\": \"var\"}, ",
+                "{\"kind\": \"Whitespace\", \"index\": 3, \"len\": 1, \"lexeme\": \" \"}, ",
+                "{\"kind\": \"Identifier\", \"index\": 4, \"len\": 1, \"lexeme\": \"s\"}, ",
+                "{\"kind\": \"Whitespace\", \"index\": 5, \"len\": 1, \"lexeme\": \" \"}, ",
+                "{\"kind\": \"Assign\", \"index\": 6, \"len\": 1, \"lexeme\": \"=\"}, ",
+                "{\"kind\": \"Whitespace\", \"index\": 7, \"len\": 1, \"lexeme\": \" \"}, ",
+                "{\"kind\": \"Str\", \"index\": 8, \"len\": 5, \"lexeme\": \"\\\"a\\tb\\\"\"}, ",
+                "{\"kind\": \"Semicolon\", \"index\": 13, \"len\": 1, \"lexeme\": \";\"}, ",
+                "{\"kind\": \"Whitespace\", \"index\": 14, \"len\": 1, \"lexeme\": \"\\t\"}, ",
+                "{\"kind\": \"Comment\", \"index\": 15, \"len\": 5, \"lexeme\": \"// hi\"}]"
+            )
+        );
+        assert_eq!(tokens_json(""), "[]");
+    }
+
+    #[test]
+    fn tokenize_unknown_characters() {
+        let kinds: Vec<_> = tokenize("1 # é€").map(|t| (t.kind, t.len)).collect();
+        assert_eq!(
+            kinds[2..],
+            [
+                (Unknown('#'), 1),
+                (Whitespace, 1),
+                (Identifier(String::from("é")), 2),
+                (Unknown('€'), 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_at() {
+        let kinds: Vec<_> = tokenize("@memoize fun")
+            .filter(|t| t.kind != Whitespace)
+            .map(|t| t.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                At,
+                Identifier(String::from("memoize")),
+                Keyword(KeywordKind::Fun)
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_hex_floats() {
+        let kinds: Vec<_> = tokenize("0x1p4 0x1.8p3 0X.8P+1 0x1p-2 0xff")
+            .filter(|t| t.kind != Whitespace)
+            .map(|t| t.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                Literal(Number {
+                    value: 16.0,
+                    is_int: false
+                }),
+                Literal(Number {
+                    value: 12.0,
+                    is_int: false
+                }),
+                Literal(Number {
+                    value: 1.0,
+                    is_int: false
+                }),
+                Literal(Number {
+                    value: 0.25,
+                    is_int: false
+                }),
+                Literal(Number {
+                    value: 255.0,
+                    is_int: false
+                }),
+            ]
+        );
+
+        for malformed in &["0x1.p", "0x1.8", "0x", "0x1p+"] {
+            let token = tokenize(malformed).next().unwrap();
+            assert_eq!(token.kind, Literal(MalformedNumber(malformed.to_string())));
+            assert_eq!(token.len, malformed.len());
+        }
+    }
+
+    #[test]
+    fn tokenize_hex_and_binary_integers() {
+        let kinds: Vec<_> = tokenize("0xff 0XFF 0xFf 0b11 0B1010 0b0")
+            .filter(|t| t.kind != Whitespace)
+            .map(|t| t.kind)
+            .collect();
+        let numbers: Vec<_> = [255.0, 255.0, 255.0, 3.0, 10.0, 0.0]
+            .iter()
+            .map(|&value| {
+                Literal(Number {
+                    value,
+                    is_int: false,
+                })
+            })
+            .collect();
+        assert_eq!(kinds, numbers);
+
+        let too_long = format!("0b1{}", "0".repeat(64));
+        for malformed in &["0b", "0b102", "0B2", too_long.as_str()] {
+            let token = tokenize(malformed).next().unwrap();
+            assert_eq!(token.kind, Literal(MalformedNumber(malformed.to_string())));
+            assert_eq!(token.len, malformed.len());
+        }
+    }
+
+    #[test]
+    fn tokenize_scientific_notation() {
+        let kinds: Vec<_> = tokenize("1e3 1.5e-2 2E+4 1e")
+            .filter(|t| t.kind != Whitespace)
+            .map(|t| t.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                Literal(Number {
+                    value: 1000.0,
+                    is_int: false
+                }),
+                Literal(Number {
+                    value: 0.015,
+                    is_int: false
+                }),
+                Literal(Number {
+                    value: 20000.0,
+                    is_int: false
+                }),
+                Literal(Number {
+                    value: 1.0,
+                    is_int: false
+                }),
+                Identifier(String::from("e")),
+            ]
+        );
+
+        // Without digits after it the `e` and its sign are left alone
+        let kinds: Vec<_> = tokenize("2e+x").map(|t| (t.kind, t.len)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    Literal(Number {
+                        value: 2.0,
+                        is_int: false
+                    }),
+                    1
+                ),
+                (Identifier(String::from("e")), 1),
+                (Plus, 1),
+                (Identifier(String::from("x")), 1),
+            ]
+        );
+        assert_eq!(
+            tokenize("1e3n").next().unwrap().kind,
+            Literal(MalformedNumber(String::from("1e3n")))
+        );
+    }
+
+    #[test]
+    fn tokenize_integer_suffix() {
+        let kinds: Vec<_> = tokenize("3n 3 3.5n 0xFFn 0b1n")
+            .filter(|t| t.kind != Whitespace)
+            .map(|t| t.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                Literal(Number {
+                    value: 3.0,
+                    is_int: true
+                }),
+                Literal(Number {
+                    value: 3.0,
+                    is_int: false
+                }),
+                Literal(MalformedNumber(String::from("3.5n"))),
+                Literal(Number {
+                    value: 255.0,
+                    is_int: true
+                }),
+                Literal(Number {
+                    value: 1.0,
+                    is_int: true
+                }),
+            ]
+        );
+        // Only whole hexadecimal numbers take the suffix
+        for malformed in ["0x1.8n", "0x1p4n", "0b2n"] {
+            let token = tokenize(malformed).next().unwrap();
+            assert_eq!(token.kind, Literal(MalformedNumber(malformed.to_string())));
+            assert_eq!(token.len, malformed.len());
+        }
+    }
+
+    #[test]
+    fn tokenize_string_escapes() {
+        for (code, value) in [
+            (r#""line1\nline2""#, "line1\nline2"),
+            (r#""a\tb""#, "a\tb"),
+            (r#""a\rb""#, "a\rb"),
+            (r#""back\\slash""#, "back\\slash"),
+            (r#""he said \"hi\"""#, "he said \"hi\""),
+            (r#""nul\0""#, "nul\0"),
+        ] {
+            let token = tokenize(code).next().unwrap();
+            assert_eq!(
+                token.kind,
+                Literal(Str {
+                    terminated: true,
+                    value: String::from(value)
+                })
+            );
+            assert_eq!(token.len, code.len());
+        }
+
+        // Unknown escapes still consume the whole string
+        let kinds: Vec<_> = tokenize(r#""a\qb" 1"#)
+            .filter(|t| t.kind != Whitespace)
+            .map(|t| t.kind)
+            .collect();
+        assert_eq!(
+            kinds[0],
+            Literal(MalformedString(String::from(r#""a\qb""#)))
+        );
+        assert_eq!(kinds.len(), 2);
+        // An escaped quote doesn't close the string
+        assert_eq!(
+            tokenize(r#""a\""#).next().unwrap().kind,
+            Literal(Str {
+                terminated: false,
+                value: String::from("a\"")
+            })
+        );
+    }
+
+    #[test]
+    fn tokenize_unicode_escapes() {
+        for (code, value) in [
+            (r#""caf\u{e9}""#, "café"),
+            (r#""\u{1F600}!""#, "\u{1F600}!"),
+            (r#""\u{0}""#, "\0"),
+        ] {
+            let token = tokenize(code).next().unwrap();
+            assert_eq!(
+                token.kind,
+                Literal(Str {
+                    terminated: true,
+                    value: String::from(value)
+                })
+            );
+            assert_eq!(token.len, code.len());
+        }
+
+        for code in [
+            r#""\u{110000}""#,
+            r#""\u{D800}""#,
+            r#""\u{1234567}""#,
+            r#""\u{}""#,
+            r#""\u{zz}""#,
+            r#""\u1F600""#,
+        ] {
+            let token = tokenize(code).next().unwrap();
+            assert_eq!(token.kind, Literal(MalformedString(String::from(code))));
+            assert_eq!(token.len, code.len());
+        }
+    }
+
+    #[test]
+    fn split_chunks() {
+        let code = "var a = 1; print a;\nfun f(x) {\n  return x;\n}\nif (a)\n  print \"a;\n}\";\n\n// else\nelse {}\nprint f(2) // not yet;\n  ;";
+        let chunks: Vec<_> = Chunks::new(code.as_bytes())
+            .map(|chunk| chunk.unwrap())
+            .map(|chunk| (chunk.line, chunk.code))
+            .collect();
+        assert_eq!(
+            chunks,
+            vec![
+                (1, String::from("var a = 1; print a;\n")),
+                (2, String::from("fun f(x) {\n  return x;\n}\n")),
+                (
+                    5,
+                    String::from("if (a)\n  print \"a;\n}\";\n\n// else\nelse {}\n")
+                ),
+                (11, String::from("print f(2) // not yet;\n  ;")),
+            ]
+        );
+        // Whatever is left at the end is a chunk too, complete or not
+        let chunks: Vec<_> = Chunks::new("print 1;\nprint (".as_bytes())
+            .map(|chunk| chunk.unwrap().code)
+            .collect();
+        assert_eq!(chunks, vec!["print 1;\n", "print ("]);
+        assert_eq!(Chunks::new("".as_bytes()).count(), 0);
+    }
+
+    #[test]
+    fn tokenize_custom_keywords() {
+        let keywords: Keywords = vec![
+            (String::from("imprimir"), KeywordKind::Print),
+            (String::from("verdadero"), KeywordKind::True),
+        ]
+        .into_iter()
+        .collect();
+        let kinds: Vec<_> = tokenize_with_keywords("imprimir verdadero print", &keywords)
+            .filter(|t| t.kind != Whitespace)
+            .map(|t| t.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                Keyword(KeywordKind::Print),
+                Keyword(KeywordKind::True),
+                Identifier(String::from("print")),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_eof() {
+        assert_eq!(tokenize("1 ").last().unwrap().kind, Whitespace);
+        let eof = tokenize_with_eof("1 ").nth(2).unwrap();
+        assert_eq!((eof.kind, eof.index, eof.len), (Eof, 2, 0));
+        assert_eq!(tokenize_with_eof("1 ").count(), 3);
+        let spans: Vec<_> = tokenize_with_eof("")
+            .map(|t| (t.kind, t.index, t.len))
+            .collect();
+        assert_eq!(spans, vec![(Eof, 0, 0)]);
+    }
+
+    #[test]
+    fn tokenize_line_and_column() {
+        let code = "var été = 1;\n  print été;\r\n\"a\nb\" x";
+        let positions: Vec<_> = tokenize(code)
+            .filter(|t| t.kind != Whitespace)
+            .map(|t| (t.kind, t.line, t.column, t.index, t.len))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                (Keyword(KeywordKind::Var), 1, 1, 0, 3),
+                (Identifier(String::from("été")), 1, 5, 4, 5),
+                (Assign, 1, 9, 10, 1),
+                (
+                    Literal(Number {
+                        value: 1.0,
+                        is_int: false
+                    }),
+                    1,
+                    11,
+                    12,
+                    1
+                ),
+                (Semicolon, 1, 12, 13, 1),
+                (Keyword(KeywordKind::Print), 2, 3, 17, 5),
+                (Identifier(String::from("été")), 2, 9, 23, 5),
+                (Semicolon, 2, 12, 28, 1),
+                (
+                    Literal(Str {
+                        terminated: true,
+                        value: String::from("a\nb")
+                    }),
+                    3,
+                    1,
+                    31,
+                    5
+                ),
+                (Identifier(String::from("x")), 4, 4, 37, 1),
+            ]
+        );
+        assert_eq!(line_column(code, 23), (2, 9));
+        let eof = tokenize_with_eof(code).last().unwrap();
+        assert_eq!((eof.line, eof.column, eof.index), (4, 5, code.len()));
+    }
+
+    #[test]
+    fn tokenize_mixed_whitespace() {
+        // Tabs and spaces are a byte each, the line separator takes 3 bytes and NEXT LINE 2
+        let code = "\t 1 \u{2028}\t+\u{85}x";
+        let spans: Vec<_> = tokenize(code)
+            .map(|t| (t.kind, t.index, t.len, t.column))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (Whitespace, 0, 2, 1),
+                (
+                    Literal(Number {
+                        value: 1.0,
+                        is_int: false
+                    }),
+                    2,
+                    1,
+                    3
+                ),
+                (Whitespace, 3, 5, 4),
+                (Plus, 8, 1, 7),
+                (Whitespace, 9, 2, 8),
+                (Identifier(String::from("x")), 11, 1, 9),
+            ]
+        );
+        // Spans always fall on character boundaries of the source
+        for (_, index, len, _) in spans {
+            assert!(code.get(index..index + len).is_some());
+        }
+        // Only '\n' starts a new line
+        assert_eq!(line_column(code, code.len()), (1, 10));
+    }
 }
@@ -0,0 +1,37 @@
+//! Lexer, parser and tree-walking interpreter for Lox, shared by the `lox` binary and anything
+//! embedding the language
+
+pub mod ast;
+pub mod config;
+pub mod diagnostics;
+pub mod interpreter;
+pub mod lexer;
+mod natives;
+pub mod optimizer;
+pub mod parser;
+pub mod warnings;
+
+use std::{cell::RefCell, error::Error, rc::Rc};
+
+use ast::Stmt;
+use config::Config;
+use interpreter::{Environment, Interpreter};
+use lexer::TokenKind;
+use parser::LoxSyntaxError;
+
+/// Parses and optimizes a whole program
+pub fn parse(code: &str, config: &Config) -> Result<Vec<Stmt>, Vec<LoxSyntaxError>> {
+    let mut tokens = lexer::tokenize_with_eof(code)
+        .filter(|t| t.kind != TokenKind::Whitespace)
+        .peekable();
+    parser::parse(&mut tokens, config).map(optimizer::optimize)
+}
+
+/// Runs `source` with `env` as its global scope, so declarations stay around for the next call.
+/// Errors are rendered with the line and column they happened at.
+pub fn interpret(source: &str, env: Rc<RefCell<Environment>>) -> Result<(), Box<dyn Error>> {
+    let ast = parse(source, &Config::default())
+        .map_err(|errors| parser::render_errors(&errors, source))?;
+    let interpreter = Interpreter::new().with_globals(env);
+    Ok(interpreter.interpret(&ast).map_err(|e| e.render(source))?)
+}
@@ -1,72 +1,564 @@
-mod ast;
-mod interpreter;
-mod lexer;
-mod parser;
-
 use std::{
-    cell::RefCell,
-    io::{stderr, Write},
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, Seek, SeekFrom, Write},
     rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender},
+        Arc,
+    },
 };
 
 use clap::Clap;
 
-use crate::lexer::TokenKind;
-use interpreter::{Environment, Interpretable};
+use lox::{
+    ast,
+    ast::Stmt,
+    config::Config,
+    diagnostics,
+    interpreter::{Coverage, Interpreter, Snapshot},
+    lexer::{self, TokenKind},
+    parse,
+    parser::{self, LoxSyntaxError},
+    warnings,
+};
+
+/// Number of distinct REPL lines whose AST is kept around
+const REPL_CACHE_CAPACITY: usize = 32;
 
 #[derive(Clap, Debug)]
 #[clap(name = "lox")]
 struct Input {
-    file_path: Option<String>,
+    /// Files to run in order, sharing the same global scope
+    files: Vec<String>,
+    /// Parse `,` outside of argument lists as the comma operator instead of rejecting it
+    #[clap(long)]
+    comma_operator: bool,
+    /// Let statements end without `;` when nothing else continues them
+    #[clap(long)]
+    lenient_semicolons: bool,
+    /// Stop before running a file that has warnings
+    #[clap(long)]
+    deny_warnings: bool,
+    /// Share the nodes of repeated `true`, `false`, `nil` and small whole number literals
+    #[clap(long)]
+    intern_literals: bool,
+    /// Turn strings into numbers in arithmetic, so `"3" + 4` is 7 instead of "34"
+    #[clap(long)]
+    coerce: bool,
+    /// After running each file, list its statements that never ran. Files are read whole.
+    #[clap(long)]
+    coverage: bool,
+    /// Print a JSON description of the AST types and exit
+    #[clap(long)]
+    ast_json_schema: bool,
+    /// Print the tokens of each file as a JSON array instead of running them
+    #[clap(long)]
+    emit_tokens_json: bool,
+    /// Print a longer explanation of an error or warning code, like W001, and exit
+    #[clap(long, value_name = "CODE")]
+    explain: Option<String>,
+    /// Size in bytes from which files are run a chunk of statements at a time [default: 1048576]
+    #[clap(long, value_name = "BYTES")]
+    streaming_threshold: Option<u64>,
 }
 
+/// Stack of the thread running programs. Parsing and evaluation recurse on nested expressions,
+/// so this bounds how deep programs can nest before the Rust stack runs out.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+/// Files from this size up are run a chunk of statements at a time instead of read whole
+const STREAMING_THRESHOLD: u64 = 1024 * 1024;
+/// Exit code for programs refused because of their warnings, as `EX_DATAERR` in sysexits.h
+const EXIT_DENIED_WARNINGS: i32 = 65;
+/// Exit code for programs stopped by a runtime error, as `EX_SOFTWARE` in sysexits.h
+const EXIT_RUNTIME_ERROR: i32 = 70;
+/// Exit code for files that can't be read, as `EX_IOERR` in sysexits.h
+const EXIT_IO_ERROR: i32 = 74;
+/// Nesting allowed with `STACK_SIZE`, low enough to be reported as a syntax error well before the
+/// stack overflows, which would abort the process
+const MAX_DEPTH: usize = 2000;
+/// Calls allowed with `STACK_SIZE`, leaving each one 64 KiB, a few times what a call through a
+/// handful of nested statements takes, so runaway recursion is reported as a runtime error instead
+/// of overflowing the stack
+const MAX_CALL_DEPTH: usize = STACK_SIZE / (64 * 1024);
+
 fn main() {
     let input = Input::parse();
-    if let Some(path) = input.file_path {
-        run_file(path).unwrap();
-    } else {
-        repl()
+    if input.ast_json_schema {
+        println!("{}", ast::json_schema());
+        return;
+    }
+    if let Some(code) = &input.explain {
+        match diagnostics::explain(code) {
+            Some(explanation) => println!("{}", explanation),
+            None => {
+                eprintln!("Error: {} is not a known error code", code);
+                std::process::exit(1)
+            }
+        }
+        return;
+    }
+    if input.emit_tokens_json {
+        let interpreter = Interpreter::new();
+        for file_path in &input.files {
+            let code = std::fs::read_to_string(file_path)
+                .map_err(|e| report_io_error(&interpreter, file_path, e))
+                .unwrap_or_else(|code| std::process::exit(code));
+            println!("{}", lexer::tokens_json(&code));
+        }
+        return;
+    }
+    let config = Config {
+        comma_operator: input.comma_operator,
+        max_depth: MAX_DEPTH,
+        max_call_depth: MAX_CALL_DEPTH,
+        lenient_semicolons: input.lenient_semicolons,
+        deny_warnings: input.deny_warnings,
+        echo_expression_values: false,
+        intern_literals: input.intern_literals,
+        coerce: input.coerce,
+        ..Config::default()
+    };
+    let streaming_threshold = input.streaming_threshold.unwrap_or(STREAMING_THRESHOLD);
+    let worker = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            if input.files.is_empty() {
+                repl(config);
+                Ok(())
+            } else {
+                run_files(input.files, &config, streaming_threshold, input.coverage)
+            }
+        })
+        .expect("Error starting the interpreter thread");
+    match worker.join() {
+        Ok(Ok(())) => {}
+        Ok(Err(code)) => std::process::exit(code),
+        // The panic message was already printed by the default hook
+        Err(_) => {
+            eprintln!("Error: the interpreter stopped unexpectedly");
+            std::process::exit(1)
+        }
+    }
+}
+
+/// Runs every file in a single global environment so later files can use the declarations of the
+/// earlier ones. Stops at the first file that fails, with the exit code for the failure.
+fn run_files(
+    file_paths: Vec<String>,
+    config: &Config,
+    streaming_threshold: u64,
+    coverage: bool,
+) -> Result<(), i32> {
+    let mut interpreter = Interpreter::new().with_config(config.clone());
+    let mut streaming_threshold = streaming_threshold;
+    if coverage {
+        interpreter = interpreter.with_coverage();
+        // Coverage is told against the whole tree of the file
+        streaming_threshold = u64::MAX;
+    }
+    for file_path in file_paths {
+        run_file(&file_path, &interpreter, config, streaming_threshold)?;
     }
+    Ok(())
 }
 
-fn run_file(file_path: String) -> Result<(), ()> {
-    let mut code = std::fs::read_to_string(file_path).expect("Error reading file");
-    let env = Rc::new(RefCell::new(Environment::new()));
+/// Warnings are reported before running the file, which doesn't run at all if they are denied.
+/// Files of `streaming_threshold` bytes and up are handed to `run_file_streaming`.
+fn run_file(
+    file_path: &str,
+    interpreter: &Interpreter,
+    config: &Config,
+    streaming_threshold: u64,
+) -> Result<(), i32> {
+    let size = std::fs::metadata(file_path).map_or(0, |m| m.len());
+    if size >= streaming_threshold {
+        return run_file_streaming(file_path, interpreter, config);
+    }
+    let code = std::fs::read_to_string(file_path)
+        .map_err(|e| report_io_error(interpreter, file_path, e))?;
 
-    execute(&mut code, env).unwrap_or_else(|e| {
-        let stde = stderr();
-        let mut stdew = stde.lock();
-        stdew.write_all(&format!("{}\n", e).into_bytes()).unwrap();
+    let ast = parse(&code, config).map_err(|errors| {
+        report_syntax_errors(interpreter, &errors, &code, 1);
+        1
+    })?;
+    let warnings = warnings::check(&ast);
+    for warning in &warnings {
+        interpreter.report(&warning.render(&code));
+    }
+    if config.deny_warnings && !warnings.is_empty() {
+        return Err(EXIT_DENIED_WARNINGS);
+    }
+    let result = interpreter.interpret(&ast).map_err(|e| {
+        report_with_snippet(interpreter, &e.render(&code), &code, 1, e.span());
+        EXIT_RUNTIME_ERROR
     });
+    if let Some(coverage) = interpreter.coverage(&ast) {
+        report_coverage(interpreter, file_path, &coverage, &code);
+    }
+    result
+}
+
+/// Lists the statements that never ran with the source quoted below each. Statements of a file
+/// that only ran when called from later files count as never run.
+fn report_coverage(interpreter: &Interpreter, file_path: &str, coverage: &Coverage, code: &str) {
+    interpreter.report(&format!(
+        "Coverage of {}: {} of {} statements ran",
+        file_path,
+        coverage.statements - coverage.uncovered.len(),
+        coverage.statements
+    ));
+    for &(index, len) in &coverage.uncovered {
+        let (line, column) = lexer::line_column(code, index);
+        let message = format!("Never ran at {}:{}", line, column);
+        report_with_snippet(interpreter, &message, code, 1, (index, len));
+    }
+}
+
+/// Like `run_file` but holding a chunk of statements in memory at a time. The file is read twice,
+/// first reporting every syntax error and warning, so nothing runs when there are errors or denied
+/// warnings, then running it.
+///
+/// Chunks only end after a `;` or `}`, so with `lenient_semicolons` the statements left without
+/// `;` are held together up to the next one that has it, the whole file if none does.
+fn run_file_streaming(
+    file_path: &str,
+    interpreter: &Interpreter,
+    config: &Config,
+) -> Result<(), i32> {
+    let io_error = |e| report_io_error(interpreter, file_path, e);
+    let mut file = File::open(file_path).map_err(io_error)?;
+
+    let mut failed = false;
+    let mut warnings = Vec::new();
+    for chunk in lexer::Chunks::new(BufReader::new(&file)) {
+        let chunk = chunk.map_err(io_error)?;
+        match parse(&chunk.code, config) {
+            // Warning passes don't look past a top level statement, which chunks never split, so
+            // they find the same warnings as over the whole file
+            Ok(ast) => warnings.extend(
+                warnings::check(&ast)
+                    .iter()
+                    .map(|w| w.render_at(&chunk.code, chunk.line)),
+            ),
+            Err(errors) => {
+                report_syntax_errors(interpreter, &errors, &chunk.code, chunk.line);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        return Err(1);
+    }
+    for warning in &warnings {
+        interpreter.report(warning);
+    }
+    if config.deny_warnings && !warnings.is_empty() {
+        return Err(EXIT_DENIED_WARNINGS);
+    }
+
+    file.seek(SeekFrom::Start(0)).map_err(io_error)?;
+    for chunk in lexer::Chunks::new(BufReader::new(&file)) {
+        let chunk = chunk.map_err(io_error)?;
+        let ast = parse(&chunk.code, config).map_err(|errors| {
+            // Only when the file changed since the first read
+            report_syntax_errors(interpreter, &errors, &chunk.code, chunk.line);
+            1
+        })?;
+        interpreter.interpret(&ast).map_err(|e| {
+            let message = e.render_at(&chunk.code, chunk.line);
+            report_with_snippet(interpreter, &message, &chunk.code, chunk.line, e.span());
+            EXIT_RUNTIME_ERROR
+        })?;
+    }
     Ok(())
 }
 
-fn repl() {
-    let stdin = std::io::stdin();
+fn report_io_error(interpreter: &Interpreter, file_path: &str, error: std::io::Error) -> i32 {
+    interpreter.report(&format!("Error: can't read {}: {}", file_path, error));
+    EXIT_IO_ERROR
+}
+
+/// Reports each error with the source it points at quoted below. `code` starts at line
+/// `first_line` of the file.
+fn report_syntax_errors(
+    interpreter: &Interpreter,
+    errors: &[LoxSyntaxError],
+    code: &str,
+    first_line: usize,
+) {
+    for error in errors {
+        let message = error.render_at(code, first_line);
+        report_with_snippet(interpreter, &message, code, first_line, error.span());
+    }
+}
+
+/// Reports `message` with the `span` of `code` it is about quoted below
+fn report_with_snippet(
+    interpreter: &Interpreter,
+    message: &str,
+    code: &str,
+    first_line: usize,
+    (index, len): (usize, usize),
+) {
+    interpreter.report(&format!(
+        "{}\n{}",
+        message,
+        diagnostics::snippet_at(code, first_line, index, len)
+    ));
+}
+
+fn repl(config: Config) {
+    let config = Config {
+        echo_expression_values: true,
+        ..config
+    };
+    // Ctrl-C stops the running statement, or cancels the line being typed, instead of killing
+    // the process
+    let interrupted = Arc::new(AtomicBool::new(false));
+    // Stdin is the REPL's own, programs reading lines from it get to its end at once
+    let interpreter = Interpreter::new()
+        .with_config(config.clone())
+        .with_interrupt(interrupted.clone())
+        .with_input(std::io::empty());
+    let mut cache = ParseCache::new(REPL_CACHE_CAPACITY, config.clone());
+    // Globals as they were before the last line ran, for `:changes`
+    let mut previous_globals = interpreter.globals_snapshot();
+
+    // Lines are read on their own thread so an interrupt doesn't wait for the pending read
+    let (sender, lines) = mpsc::channel();
+    let (handler_flag, handler_sender) = (interrupted.clone(), sender.clone());
+    ctrlc::set_handler(move || {
+        handler_flag.store(true, Ordering::SeqCst);
+        let _ = handler_sender.send(Line::Interrupted);
+    })
+    .expect("Error installing the Ctrl-C handler");
+    std::thread::spawn(move || read_lines(std::io::stdin().lock(), &sender));
+
     println!("Running repl");
-    let env = Rc::new(RefCell::new(Environment::new()));
+    prompt();
+    for line in lines {
+        match line {
+            Line::Input(buffer) if buffer.trim_start().starts_with(':') => {
+                match meta_command(&buffer, &interpreter, &config, &previous_globals) {
+                    Ok(output) => println!("{}", output),
+                    Err(e) => interpreter.report(&*e),
+                }
+            }
+            Line::Input(buffer) => {
+                previous_globals = interpreter.globals_snapshot();
+                execute_cached(&buffer, &mut cache, &interpreter)
+                    .unwrap_or_else(|e| interpreter.report(&*e))
+            }
+            // Unless a running loop already stopped on it, the interrupt cancelled the line
+            // being typed, which the terminal discards
+            Line::Interrupted if interrupted.swap(false, Ordering::SeqCst) => println!(),
+            Line::Interrupted => continue,
+            Line::Eof => break,
+        }
+        prompt();
+    }
+}
+
+fn prompt() {
+    print!("> ");
+    std::io::stdout().flush().expect("Error writing the prompt");
+}
 
+#[derive(Debug, PartialEq)]
+enum Line {
+    Input(String),
+    /// Ctrl-C was pressed
+    Interrupted,
+    /// Ctrl-D or the end of a piped input
+    Eof,
+}
+
+/// Sends every line of `input` until its end, or until nobody receives them
+fn read_lines(mut input: impl BufRead, lines: &Sender<Line>) {
     loop {
         let mut buffer = String::new();
-        stdin.read_line(&mut buffer).expect("Error reading input");
-        execute(&mut buffer, env.clone()).unwrap_or_else(|e| {
-            let stde = stderr();
-            let mut stdew = stde.lock();
-            stdew.write_all(&format!("{}\n", e).into_bytes()).unwrap();
-        });
+        let line = match input.read_line(&mut buffer) {
+            Ok(0) => Line::Eof,
+            Ok(_) => Line::Input(buffer),
+            Err(e) => panic!("Error reading input: {}", e),
+        };
+        let eof = line == Line::Eof;
+        if lines.send(line).is_err() || eof {
+            return;
+        }
     }
 }
 
-fn execute(
-    code: &mut str,
-    env: Rc<RefCell<Environment>>,
+fn execute_cached(
+    code: &str,
+    cache: &mut ParseCache,
+    interpreter: &Interpreter,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut tokens = lexer::tokenize(code)
-        .filter(|t| t.kind != TokenKind::Whitespace)
-        .peekable();
-    let ast = parser::parse(&mut tokens)?;
-    for stmt in ast {
-        stmt.eval(env.clone())?;
+    let ast = cache
+        .get_or_parse(code)
+        .map_err(|errors| parser::render_errors(&errors, code))?;
+    Ok(interpreter.interpret(&ast).map_err(|e| e.render(code))?)
+}
+
+/// Runs REPL commands, the lines starting with ':', and returns what they print.
+/// `previous_globals` are the globals from before the last line of code ran.
+fn meta_command(
+    line: &str,
+    interpreter: &Interpreter,
+    config: &Config,
+    previous_globals: &Snapshot,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let line = line.trim();
+    let (command, argument) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, ""),
+    };
+    match command {
+        ":type" => {
+            let mut tokens = lexer::tokenize_with_eof(argument)
+                .filter(|t| t.kind != TokenKind::Whitespace)
+                .peekable();
+            let expr =
+                parser::parse_expression(&mut tokens, config).map_err(|e| e.render(argument))?;
+            let value = interpreter
+                .evaluate(&expr)
+                .map_err(|e| e.render(argument))?;
+            Ok(value.get_type().to_string())
+        }
+        ":changes" => Ok(changes(previous_globals, &interpreter.globals_snapshot())),
+        _ => Err(format!("Unknown command {}", command).into()),
+    }
+}
+
+/// Lists the globals added (`+`) or modified (`~`) between two snapshots, sorted by name
+fn changes(before: &Snapshot, after: &Snapshot) -> String {
+    let mut changed: Vec<_> = after
+        .iter()
+        .filter_map(|(name, value)| match before.get(name) {
+            None => Some(('+', name, value)),
+            Some(previous) if previous != value => Some(('~', name, value)),
+            Some(_) => None,
+        })
+        .collect();
+    if changed.is_empty() {
+        return String::from("No changes");
+    }
+    changed.sort_by_key(|(_, name, _)| *name);
+    let lines: Vec<String> = changed
+        .into_iter()
+        .map(|(change, name, value)| match value {
+            Some(value) => format!("{} {} = {}", change, name, value.repr()),
+            None => format!("{} {}", change, name),
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// Least recently used cache of parsed REPL lines, keyed by the raw input.
+/// Source strings never change once read, so entries are only evicted when the cache is full.
+struct ParseCache {
+    capacity: usize,
+    config: Config,
+    // Most recently used entries live at the front
+    entries: VecDeque<(String, Rc<Vec<Stmt>>)>,
+}
+
+impl ParseCache {
+    fn new(capacity: usize, config: Config) -> ParseCache {
+        ParseCache {
+            capacity,
+            config,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn get_or_parse(&mut self, code: &str) -> Result<Rc<Vec<Stmt>>, Vec<LoxSyntaxError>> {
+        if let Some(position) = self.entries.iter().position(|(key, _)| key == code) {
+            let entry = self.entries.remove(position).unwrap();
+            let ast = entry.1.clone();
+            self.entries.push_front(entry);
+            return Ok(ast);
+        }
+
+        let ast = Rc::new(parse(code, &self.config)?);
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_back();
+        }
+        self.entries.push_front((code.into(), ast.clone()));
+        Ok(ast)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use std::{io::Cursor, sync::mpsc};
+
+    use super::{meta_command, parse, read_lines, Config, Line, ParseCache};
+    use lox::interpreter::Interpreter;
+
+    #[test]
+    fn parse_cache_reuses_ast() {
+        let mut cache = ParseCache::new(2, Config::default());
+        let first = cache.get_or_parse("var a = 1 + 2;").unwrap();
+        let second = cache.get_or_parse("var a = 1 + 2;").unwrap();
+        assert_eq!(first, second);
+        assert!(Rc::ptr_eq(&first, &second));
+
+        // Filling the cache evicts the least recently used line
+        cache.get_or_parse("print 1;").unwrap();
+        cache.get_or_parse("print 2;").unwrap();
+        let third = cache.get_or_parse("var a = 1 + 2;").unwrap();
+        assert_eq!(first, third);
+        assert!(!Rc::ptr_eq(&first, &third));
+    }
+
+    #[test]
+    fn lines_are_read_until_the_end() {
+        let (sender, lines) = mpsc::channel();
+        read_lines(Cursor::new("print 1;\nprint 2;\n"), &sender);
+        assert_eq!(
+            lines.try_iter().collect::<Vec<_>>(),
+            [
+                Line::Input(String::from("print 1;\n")),
+                Line::Input(String::from("print 2;\n")),
+                Line::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn type_command() {
+        let interpreter = Interpreter::new();
+        let config = Config::default();
+        let globals = interpreter.globals_snapshot();
+        let meta_command = |line| meta_command(line, &interpreter, &config, &globals);
+        assert_eq!(meta_command(":type 1 + 1").unwrap(), "number");
+        assert_eq!(meta_command(":type \"a\" + \"b\"\n").unwrap(), "string");
+        assert!(meta_command(":type 1 1").is_err());
+        assert!(meta_command(":unknown").is_err());
+    }
+
+    #[test]
+    fn changes_command() {
+        let interpreter = Interpreter::new();
+        let config = Config::default();
+        let run_line = |code: &str| {
+            let previous_globals = interpreter.globals_snapshot();
+            interpreter
+                .interpret(&parse(code, &config).unwrap())
+                .unwrap();
+            meta_command(":changes", &interpreter, &config, &previous_globals).unwrap()
+        };
+
+        assert_eq!(
+            run_line("var b = \"x\"; var a = 1;"),
+            "+ a = 1\n+ b = \"x\""
+        );
+        assert_eq!(run_line("a = 2; var c; b = \"x\";"), "~ a = 2\n+ c");
+        assert_eq!(run_line("print a;"), "No changes");
     }
-    Ok(())
 }
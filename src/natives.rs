@@ -0,0 +1,189 @@
+//! Functions implemented in Rust that are declared in the global scope of every program
+
+use std::{
+    io,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::interpreter::{Environment, FileSystem, Interpreter, LoxResult, NativeFn};
+
+pub const NATIVES: [NativeFn; 10] = [
+    NativeFn::new("clock", 0, clock),
+    NativeFn::new("print", 1, print),
+    NativeFn::new("random", 0, random),
+    NativeFn::new("read_file", 1, read_file),
+    NativeFn::new("read_line", 0, read_line),
+    NativeFn::new("repr", 1, repr),
+    NativeFn::new("round_to", 2, round_to),
+    NativeFn::new("seed", 1, seed),
+    NativeFn::new("write", 1, write),
+    NativeFn::new("write_file", 2, write_file),
+];
+
+/// Declares every native in `environment`
+pub fn install(environment: &mut Environment) {
+    environment.define_many(
+        NATIVES
+            .iter()
+            .map(|native| (native.name().into(), LoxResult::NativeFn(native.clone())))
+            .collect(),
+    );
+}
+
+/// Seconds since the Unix epoch, with sub-second precision, as the interpreter's clock tells them
+fn clock(interpreter: &Interpreter, _: &[LoxResult]) -> Result<LoxResult, String> {
+    Ok(LoxResult::Number(interpreter.now()))
+}
+
+/// Seconds since the Unix epoch by the system clock, negative if it is set before the epoch
+pub fn system_clock() -> f64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    }
+}
+
+/// A number in `[0, 1)` from the interpreter's generator, see `Interpreter::with_seed`
+fn random(interpreter: &Interpreter, _: &[LoxResult]) -> Result<LoxResult, String> {
+    Ok(LoxResult::Number(interpreter.random()))
+}
+
+/// Restarts the numbers `random` returns from the given seed, any number, and returns `nil`
+fn seed(interpreter: &Interpreter, arguments: &[LoxResult]) -> Result<LoxResult, String> {
+    match &arguments[0] {
+        LoxResult::Number(n) => {
+            interpreter.seed(n.to_bits());
+            Ok(LoxResult::Nil)
+        }
+        n => Err(format!("seed expects a number but got {}", n.get_type())),
+    }
+}
+
+/// State of the `random` generator for a seed. Mixed with splitmix64 so seeds close to each other
+/// still start unrelated sequences, and kept away from 0, which xorshift never leaves.
+pub fn random_state(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    match z ^ (z >> 31) {
+        0 => 1,
+        state => state,
+    }
+}
+
+/// Next state of a xorshift64 generator
+pub fn xorshift(mut state: u64) -> u64 {
+    state ^= state << 13;
+    state ^= state >> 7;
+    state ^= state << 17;
+    state
+}
+
+/// The files on disk
+pub struct SystemFileSystem;
+
+impl FileSystem for SystemFileSystem {
+    fn read(&self, path: &str) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn write(&self, path: &str, contents: &str) -> io::Result<()> {
+        std::fs::write(path, contents)
+    }
+}
+
+/// The contents of the file at the given path as a string
+fn read_file(interpreter: &Interpreter, arguments: &[LoxResult]) -> Result<LoxResult, String> {
+    let path = match &arguments[0] {
+        LoxResult::Str(path) => path,
+        path => {
+            return Err(format!(
+                "read_file expects a string path but got {}",
+                path.get_type()
+            ))
+        }
+    };
+    match interpreter.file_system().read(path) {
+        Ok(contents) => Ok(LoxResult::Str(contents)),
+        Err(e) => Err(format!("Can't read {}: {}", path, e)),
+    }
+}
+
+/// Replaces the contents of the file at the given path, creating it if needed, and returns `nil`
+fn write_file(interpreter: &Interpreter, arguments: &[LoxResult]) -> Result<LoxResult, String> {
+    let (path, contents) = match arguments {
+        [LoxResult::Str(path), LoxResult::Str(contents)] => (path, contents),
+        [path, contents] => {
+            return Err(format!(
+                "write_file expects two strings but got {} and {}",
+                path.get_type(),
+                contents.get_type()
+            ))
+        }
+        _ => unreachable!("The arity is checked before calling natives"),
+    };
+    match interpreter.file_system().write(path, contents) {
+        Ok(()) => Ok(LoxResult::Nil),
+        Err(e) => Err(format!("Can't write {}: {}", path, e)),
+    }
+}
+
+/// Prints its argument like the `print` statement and returns it, so values can be printed
+/// without pulling them out of the expression using them
+fn print(interpreter: &Interpreter, arguments: &[LoxResult]) -> Result<LoxResult, String> {
+    interpreter.print(&arguments[0]);
+    Ok(arguments[0].clone())
+}
+
+/// Writes its argument like `print` without ending the line, for prompts, and returns it
+fn write(interpreter: &Interpreter, arguments: &[LoxResult]) -> Result<LoxResult, String> {
+    match interpreter.write(&arguments[0]) {
+        Ok(()) => Ok(arguments[0].clone()),
+        Err(e) => Err(format!("Can't write the output: {}", e)),
+    }
+}
+
+/// The next line of the input as a string without its line ending, `nil` at the end of the input
+fn read_line(interpreter: &Interpreter, _: &[LoxResult]) -> Result<LoxResult, String> {
+    match interpreter.read_line() {
+        Ok(Some(line)) => Ok(LoxResult::Str(line)),
+        Ok(None) => Ok(LoxResult::Nil),
+        Err(e) => Err(format!("Can't read the input: {}", e)),
+    }
+}
+
+/// The quoted form of its argument, as the REPL echoes values, where strings show their quotes
+/// and escapes
+fn repr(_: &Interpreter, arguments: &[LoxResult]) -> Result<LoxResult, String> {
+    Ok(LoxResult::Str(arguments[0].repr()))
+}
+
+/// Rounds a number to the given count of decimal places by scaling it and rounding to the nearest
+/// whole number. Halfway cases round away from zero, as `f64::round` does, so `round_to(2.5, 0)`
+/// is `3` and `round_to(-2.5, 0)` is `-3`.
+fn round_to(_: &Interpreter, arguments: &[LoxResult]) -> Result<LoxResult, String> {
+    let (n, digits) = match arguments {
+        [LoxResult::Number(n), LoxResult::Number(digits)] => (*n, *digits),
+        [n, digits] => {
+            return Err(format!(
+                "round_to expects two numbers but got {} and {}",
+                n.get_type(),
+                digits.get_type()
+            ))
+        }
+        _ => unreachable!("The arity is checked before calling natives"),
+    };
+    if digits < 0.0 || digits.fract() != 0.0 {
+        return Err(format!(
+            "round_to expects a whole number of digits of 0 or more but got {}",
+            LoxResult::Number(digits)
+        ));
+    }
+    let scale = 10f64.powf(digits);
+    let scaled = n * scale;
+    // Past the precision of an f64 there is nothing left to round
+    if !scaled.is_finite() {
+        return Ok(LoxResult::Number(n));
+    }
+    Ok(LoxResult::Number(scaled.round() / scale))
+}
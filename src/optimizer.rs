@@ -0,0 +1,263 @@
+use std::rc::Rc;
+
+use crate::ast::{BinOp, Expr, Literal, Stmt, UnaryOp};
+
+/// Folds constant subexpressions of every statement. Folded nodes keep the span of the
+/// expression they replace so errors still point at the original source.
+pub fn optimize(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    stmts.into_iter().map(optimize_stmt).collect()
+}
+
+fn optimize_stmt(stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::Expression(e) => Stmt::Expression(fold(e)),
+        Stmt::Print(e) => Stmt::Print(fold(e)),
+        Stmt::Variable {
+            name,
+            initializer,
+            index,
+            len,
+        } => Stmt::Variable {
+            name,
+            initializer: initializer.map(fold),
+            index,
+            len,
+        },
+        Stmt::Block(stmts) => Stmt::Block(optimize(stmts)),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => Stmt::If {
+            condition: fold(condition),
+            then_branch: optimize_stmt(*then_branch).into(),
+            else_branch: else_branch.map(|s| optimize_stmt(*s).into()),
+        },
+        Stmt::While { condition, body } => Stmt::While {
+            condition: fold(condition),
+            body: optimize_stmt(*body).into(),
+        },
+        Stmt::Function {
+            name,
+            params,
+            attributes,
+            body,
+            index,
+            len,
+        } => Stmt::Function {
+            name,
+            params,
+            attributes,
+            // Bodies already shared with a running function are left as they are
+            body: match Rc::try_unwrap(body) {
+                Ok(body) => Rc::new(optimize(body)),
+                Err(shared) => shared,
+            },
+            index,
+            len,
+        },
+        Stmt::Return { value, index, len } => Stmt::Return {
+            value: value.map(fold),
+            index,
+            len,
+        },
+    }
+}
+
+/// Folds the expression bottom up with an explicit stack instead of recursion, like
+/// `eval_operators` in the interpreter, so long operator chains don't cost a Rust frame per
+/// operator
+pub fn fold(expr: Expr) -> Expr {
+    enum Step {
+        Fold(Expr),
+        // Puts back the folded subexpressions on top of `folded`, then folds the expression itself
+        Rebuild(Expr, usize),
+    }
+    let mut steps = vec![Step::Fold(expr)];
+    let mut folded = Vec::new();
+    while let Some(step) = steps.pop() {
+        match step {
+            Step::Fold(mut expr) => {
+                let mut children = Vec::new();
+                expr.for_each_child(|child| children.push(Step::Fold(child.take())));
+                steps.push(Step::Rebuild(expr, children.len()));
+                // Reversed so they are folded in order and end up in order on `folded`
+                steps.extend(children.into_iter().rev());
+            }
+            Step::Rebuild(mut expr, children) => {
+                let mut children = folded.split_off(folded.len() - children).into_iter();
+                expr.for_each_child(|child| *child = children.next().expect("Missing child"));
+                folded.push(fold_node(expr));
+            }
+        }
+    }
+    folded.pop().expect("Missing result")
+}
+
+/// Replaces an operator on literals with the literal it yields, its operands already folded
+fn fold_node(expr: Expr) -> Expr {
+    let value = match &expr {
+        Expr::Unary {
+            operator, right, ..
+        } => match (operator, literal(right)) {
+            (UnaryOp::Negate, Some(Literal::Number(n))) => Literal::Number(-n),
+            (UnaryOp::LogicNegate, Some(Literal::True)) => Literal::False,
+            (UnaryOp::LogicNegate, Some(Literal::False)) => Literal::True,
+            _ => return expr,
+        },
+        Expr::Binary {
+            left,
+            operator,
+            right,
+            ..
+        } => match (literal(left), operator, literal(right)) {
+            (Some(Literal::Str(l)), BinOp::Sum, Some(Literal::Str(r))) => {
+                Literal::Str(format!("{}{}", l, r))
+            }
+            _ => return expr,
+        },
+        _ => return expr,
+    };
+    Expr::Literal {
+        value: value.into(),
+        index: expr.index(),
+        len: expr.len(),
+    }
+}
+
+/// The value of a literal expression
+fn literal(expr: &Expr) -> Option<&Literal> {
+    match expr {
+        Expr::Literal { value, .. } => Some(value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fold;
+    use crate::ast::{BinOp, Expr, Literal, UnaryOp};
+    use crate::config::Config;
+    use crate::lexer::{tokenize, TokenKind};
+    use crate::parser::parse;
+
+    fn fold_expression(code: &str) -> Expr {
+        let mut tokens = tokenize(code)
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .peekable();
+        match parse(&mut tokens, &Config::default()).unwrap().remove(0) {
+            crate::ast::Stmt::Expression(e) => fold(e),
+            s => panic!("Expected an expression statement but got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn fold_unary_literals() {
+        assert_eq!(
+            fold_expression("-5;"),
+            Expr::Literal {
+                value: Literal::Number(-5.0).into(),
+                index: 0,
+                len: 2,
+            }
+        );
+        assert_eq!(
+            fold_expression("- -5;"),
+            Expr::Literal {
+                value: Literal::Number(5.0).into(),
+                index: 0,
+                len: 4,
+            }
+        );
+        assert_eq!(
+            fold_expression("!true;"),
+            Expr::Literal {
+                value: Literal::False.into(),
+                index: 0,
+                len: 5,
+            }
+        );
+        assert_eq!(
+            fold_expression("!!false;"),
+            Expr::Literal {
+                value: Literal::False.into(),
+                index: 0,
+                len: 7,
+            }
+        );
+    }
+
+    #[test]
+    fn keep_unary_variables() {
+        assert_eq!(
+            fold_expression("-x;"),
+            Expr::Unary {
+                operator: UnaryOp::Negate,
+                right: Expr::Variable {
+                    value: String::from("x"),
+                    index: 1,
+                    len: 1,
+                }
+                .into(),
+                index: 0,
+                len: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn fold_string_concatenation() {
+        assert_eq!(
+            fold_expression("\"a\" + \"b\";"),
+            Expr::Literal {
+                value: Literal::Str(String::from("ab")).into(),
+                index: 0,
+                len: 9,
+            }
+        );
+        assert_eq!(
+            fold_expression("\"a\" + \"b\" + \"c\";"),
+            Expr::Literal {
+                value: Literal::Str(String::from("abc")).into(),
+                index: 0,
+                len: 15,
+            }
+        );
+    }
+
+    #[test]
+    fn keep_concatenation_with_variables() {
+        assert_eq!(
+            fold_expression("\"a\" + x + \"b\";"),
+            Expr::Binary {
+                left: Expr::Binary {
+                    left: Expr::Literal {
+                        value: Literal::Str(String::from("a")).into(),
+                        index: 0,
+                        len: 3,
+                    }
+                    .into(),
+                    operator: BinOp::Sum,
+                    right: Expr::Variable {
+                        value: String::from("x"),
+                        index: 6,
+                        len: 1,
+                    }
+                    .into(),
+                    index: 0,
+                    len: 7,
+                }
+                .into(),
+                operator: BinOp::Sum,
+                right: Expr::Literal {
+                    value: Literal::Str(String::from("b")).into(),
+                    index: 10,
+                    len: 3,
+                }
+                .into(),
+                index: 0,
+                len: 13,
+            }
+        );
+    }
+}
@@ -2,10 +2,13 @@ use std::{
     convert::{TryFrom, TryInto},
     fmt::Display,
     iter::Peekable,
+    rc::Rc,
 };
 
 use crate::{
     ast::Stmt,
+    config::Config,
+    diagnostics,
     lexer::{self, KeywordKind},
 };
 use crate::{
@@ -15,19 +18,55 @@ use crate::{
 
 #[derive(Debug)]
 pub struct LoxSyntaxError {
+    /// Stable identifier of the kind of error, explained by `diagnostics::explain`
+    code: &'static str,
     message: String,
     index: usize,
     len: usize,
 }
 
+impl LoxSyntaxError {
+    /// Like the `Display` form but locating the error by line and column within `code`
+    pub fn render(&self, code: &str) -> String {
+        self.render_at(code, 1)
+    }
+
+    /// Like `render` for `code` starting at line `first_line` of a larger source
+    pub fn render_at(&self, code: &str, first_line: usize) -> String {
+        diagnostics::locate(
+            "Error",
+            self.code,
+            &self.message,
+            self.index,
+            code,
+            first_line,
+        )
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    /// Byte index and length of the source the error points at
+    pub fn span(&self) -> (usize, usize) {
+        (self.index, self.len)
+    }
+}
+
 impl std::error::Error for LoxSyntaxError {}
 
+/// Renders every error on its own line, as `LoxSyntaxError::render` does
+pub fn render_errors(errors: &[LoxSyntaxError], code: &str) -> String {
+    let rendered: Vec<String> = errors.iter().map(|e| e.render(code)).collect();
+    rendered.join("\n")
+}
+
 impl Display for LoxSyntaxError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         write!(
             f,
-            "Error: {} at {} until {}",
-            self.message, self.index, self.len
+            "Error[{}]: {} at {} until {}",
+            self.code, self.message, self.index, self.len
         )
     }
 }
@@ -68,18 +107,99 @@ impl TryFrom<lexer::Token> for ast::UnaryOp {
     }
 }
 
+/// Parses every statement, carrying on after a syntax error from the start of the next
+/// statement so all the errors of the program are reported at once
 pub fn parse<P: Iterator<Item = lexer::Token> + Clone>(
     tokens: &mut Peekable<P>,
-) -> Result<Vec<ast::Stmt>, LoxSyntaxError> {
+    config: &Config,
+) -> Result<Vec<ast::Stmt>, Vec<LoxSyntaxError>> {
     let mut statements = Vec::new();
-    while tokens.peek().is_some() {
-        statements.push(declaration(tokens)?)
+    let mut errors = Vec::new();
+    while !at_end(tokens) {
+        match declaration(tokens, config, 0) {
+            Ok(statement) => match top_level_return(&statement) {
+                Some(error) => errors.push(error),
+                None => statements.push(statement),
+            },
+            Err(error) => {
+                errors.push(error);
+                synchronize(tokens);
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(statements)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Discards the rest of a statement that failed to parse, stopping after the next `;` or before
+/// the next keyword that starts a statement
+fn synchronize<P: Iterator<Item = lexer::Token> + Clone>(tokens: &mut Peekable<P>) {
+    use crate::lexer::KeywordKind::*;
+    while let Some(t) = tokens.peek() {
+        match t.kind {
+            TokenKind::Semicolon => {
+                tokens.next();
+                return;
+            }
+            TokenKind::Keyword(Class | Fun | Var | For | If | While | Print | Return)
+            | TokenKind::Eof => return,
+            _ => {
+                tokens.next();
+            }
+        }
+    }
+}
+
+/// Finds a `return` in a top level statement outside of any function, where there is nothing to
+/// return from
+fn top_level_return(stmt: &Stmt) -> Option<LoxSyntaxError> {
+    match stmt {
+        Stmt::Return { index, len, .. } => Some(LoxSyntaxError {
+            code: "E007",
+            message: String::from("Can't return from top-level code"),
+            index: *index,
+            len: *len,
+        }),
+        Stmt::Block(stmts) => stmts.iter().find_map(top_level_return),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => top_level_return(then_branch)
+            .or_else(|| else_branch.as_deref().and_then(top_level_return)),
+        Stmt::While { body, .. } => top_level_return(body),
+        Stmt::Expression(_) | Stmt::Print(_) | Stmt::Variable { .. } | Stmt::Function { .. } => {
+            None
+        }
+    }
+}
+
+/// Parses a single expression, which has to span every remaining token
+pub fn parse_expression<P: Iterator<Item = lexer::Token> + Clone>(
+    tokens: &mut Peekable<P>,
+    config: &Config,
+) -> Result<ast::Expr, LoxSyntaxError> {
+    let expr = expression(tokens, config, 0)?;
+    match tokens.peek() {
+        Some(t) if t.kind != TokenKind::Eof => {
+            Err(unexpected_character(t).unwrap_or_else(|| LoxSyntaxError {
+                code: "E002",
+                message: format!("Unexpected token {:?} after the expression", t.kind),
+                index: t.index,
+                len: t.len,
+            }))
+        }
+        _ => Ok(expr),
     }
-    Ok(statements)
 }
 
 fn declaration(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<ast::Stmt, LoxSyntaxError> {
     match tokens.peek() {
         Some(t) if t.kind == TokenKind::Keyword(KeywordKind::Var) => {
@@ -87,6 +207,12 @@ fn declaration(
             let var_token = t.clone(); // Needed to avoid borrowing tokens, this variable is used for error handling
             tokens.next();
             match tokens.next() {
+                Some(t) if t.kind == TokenKind::Eof => Err(LoxSyntaxError {
+                    code: "E002",
+                    message: String::from("Expected variable name"),
+                    index: t.index,
+                    len: t.len,
+                }),
                 Some(t) => {
                     let ident_token = t.clone();
                     match ident_token.kind {
@@ -97,24 +223,37 @@ fn declaration(
                                     // var ident = <value> {TO PARSE}
                                     // var ident = {TO PARSE}
                                     tokens.next();
-                                    Some(expression(tokens)?)
+                                    Some(expression(tokens, config, depth)?)
                                 }
                                 _ => None, // var ident {TO_PARSE}
                             };
-                            match tokens.next() {
-                                Some(t) if t.kind == TokenKind::Semicolon => {
-                                    Ok(Stmt::Variable(name, value))
-                                }
-                                _ => Err(LoxSyntaxError {
-                                    message: String::from(
-                                        "Expected ';' after variable declaration",
-                                    ),
-                                    index: ident_token.index + ident_token.len,
-                                    len: 0,
+                            match statement_end(tokens, config) {
+                                Ok(()) => Ok(Stmt::Variable {
+                                    name,
+                                    initializer: value,
+                                    index: ident_token.index,
+                                    len: ident_token.len,
                                 }),
+                                // Point at whatever took the place of the ';'
+                                Err(t) => {
+                                    let (index, len) = match (t, &value) {
+                                        (Some(t), _) => (t.index, t.len),
+                                        (None, Some(value)) => (value.index() + value.len(), 0),
+                                        (None, None) => (ident_token.index + ident_token.len, 0),
+                                    };
+                                    Err(LoxSyntaxError {
+                                        code: "E001",
+                                        message: String::from(
+                                            "Expected ';' after variable declaration",
+                                        ),
+                                        index,
+                                        len,
+                                    })
+                                }
                             }
                         }
                         kind => Err(LoxSyntaxError {
+                            code: "E002",
                             message: format!("Expected variable name but found {:?}", kind),
                             index: ident_token.index,
                             len: ident_token.len,
@@ -122,32 +261,183 @@ fn declaration(
                     }
                 }
                 _ => Err(LoxSyntaxError {
+                    code: "E002",
                     message: String::from("Expected variable name"),
                     index: var_token.index + var_token.len,
                     len: 0,
                 }),
             }
         }
-        _ => statement(tokens),
+        Some(t) if t.kind == TokenKind::Keyword(KeywordKind::Fun) => {
+            function_declaration(tokens, config, depth + 1, Vec::new())
+        }
+        Some(t) if t.kind == TokenKind::At => {
+            let attributes = attributes(tokens)?;
+            function_declaration(tokens, config, depth + 1, attributes)
+        }
+        _ => statement(tokens, config, depth),
+    }
+}
+
+/// Most parameters a function can declare, matching the arguments a call can pass
+const MAX_PARAMETERS: usize = MAX_ARGUMENTS;
+
+/// `@name` attributes, as many as there are, up to the `fun` of the declaration they belong to
+fn attributes(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+) -> Result<Vec<String>, LoxSyntaxError> {
+    let mut attributes = Vec::new();
+    let mut previous = None;
+    while let Some(at) = tokens.next_if(|t| t.kind == TokenKind::At) {
+        let (name, name_token) = identifier(tokens, "Expected attribute name after '@'", &at)?;
+        attributes.push(name);
+        previous = Some(name_token);
+    }
+    match (tokens.peek(), previous) {
+        (Some(t), _) if t.kind == TokenKind::Keyword(KeywordKind::Fun) => Ok(attributes),
+        (next, previous) => {
+            let (index, len) = match (next, previous) {
+                (Some(t), _) => (t.index, t.len),
+                (None, Some(p)) => (p.index + p.len, 0),
+                (None, None) => (0, 0),
+            };
+            Err(LoxSyntaxError {
+                code: "E002",
+                message: String::from("Expected a function declaration after its attributes"),
+                index,
+                len,
+            })
+        }
+    }
+}
+
+/// `fun name(params) { body }`, with the `attributes` written before it
+fn function_declaration(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
+    attributes: Vec<String>,
+) -> Result<ast::Stmt, LoxSyntaxError> {
+    let fun_token = tokens.next().unwrap();
+    let (name, name_token) = identifier(tokens, "Expected function name", &fun_token)?;
+    let left_paren = expect(
+        tokens,
+        TokenKind::LeftParen,
+        "Expected '(' after function name",
+        &name_token,
+    )?;
+
+    let mut params = Vec::new();
+    let mut previous = left_paren;
+    if !matches_any(tokens, vec![TokenKind::RightParen]) {
+        loop {
+            let (param, param_token) = identifier(tokens, "Expected parameter name", &previous)?;
+            if params.len() == MAX_PARAMETERS {
+                return Err(LoxSyntaxError {
+                    code: "E006",
+                    message: format!("Can't have more than {} parameters", MAX_PARAMETERS),
+                    index: param_token.index,
+                    len: param_token.len,
+                });
+            }
+            params.push(param);
+            previous = param_token;
+            if !matches_any(tokens, vec![TokenKind::Comma]) {
+                break;
+            }
+            previous = tokens.next().unwrap();
+        }
+    }
+    let right_paren = expect(
+        tokens,
+        TokenKind::RightParen,
+        "Expected ')' after parameters",
+        &previous,
+    )?;
+
+    if !matches_any(tokens, vec![TokenKind::LeftBrace]) {
+        let (index, len) = tokens
+            .peek()
+            .map(|t| (t.index, t.len))
+            .unwrap_or((right_paren.index + right_paren.len, 0));
+        return Err(LoxSyntaxError {
+            code: "E002",
+            message: String::from("Expected '{' before function body"),
+            index,
+            len,
+        });
+    }
+    let body = block_statement(tokens, config, depth)?;
+    Ok(Stmt::Function {
+        name,
+        params,
+        attributes,
+        body: Rc::new(body),
+        index: name_token.index,
+        len: name_token.len,
+    })
+}
+
+/// Consumes an identifier, returning its name along with its token
+fn identifier(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    message: &str,
+    previous: &lexer::Token,
+) -> Result<(String, lexer::Token), LoxSyntaxError> {
+    match tokens.next() {
+        Some(t) => match &t.kind {
+            TokenKind::Identifier(name) => Ok((name.clone(), t)),
+            _ => Err(LoxSyntaxError {
+                code: "E002",
+                message: String::from(message),
+                index: t.index,
+                len: t.len,
+            }),
+        },
+        None => Err(LoxSyntaxError {
+            code: "E002",
+            message: String::from(message),
+            index: previous.index + previous.len,
+            len: 0,
+        }),
     }
 }
 
 fn statement(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<ast::Stmt, LoxSyntaxError> {
     match tokens.peek() {
         Some(t) if t.kind == TokenKind::Keyword(KeywordKind::Print) => {
             tokens.next();
-            print_statement(tokens)
+            print_statement(tokens, config, depth)
+        }
+        Some(t) if t.kind == TokenKind::LeftBrace => {
+            Ok(Stmt::Block(block_statement(tokens, config, depth + 1)?))
+        }
+        Some(t) if t.kind == TokenKind::Keyword(KeywordKind::If) => {
+            if_statement(tokens, config, depth + 1)
         }
-        Some(t) if t.kind == TokenKind::LeftBrace => Ok(Stmt::Block(block_statement(tokens)?)),
-        _ => expression_statement(tokens),
+        Some(t) if t.kind == TokenKind::Keyword(KeywordKind::While) => {
+            while_statement(tokens, config, depth + 1)
+        }
+        Some(t) if t.kind == TokenKind::Keyword(KeywordKind::For) => {
+            for_statement(tokens, config, depth + 1)
+        }
+        Some(t) if t.kind == TokenKind::Keyword(KeywordKind::Return) => {
+            return_statement(tokens, config, depth)
+        }
+        _ => expression_statement(tokens, config, depth),
     }
 }
 
 fn block_statement(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<Vec<ast::Stmt>, LoxSyntaxError> {
+    check_depth(tokens, config, depth)?;
     let mut stmts: Vec<ast::Stmt> = Vec::new();
     let right_paren = tokens.next().unwrap();
     loop {
@@ -156,8 +446,9 @@ fn block_statement(
                 tokens.next();
                 break;
             }
-            Some(_) => stmts.push(declaration(tokens)?),
+            Some(t) if t.kind != TokenKind::Eof => stmts.push(declaration(tokens, config, depth)?),
             _ => Err(LoxSyntaxError {
+                code: "E004",
                 message: String::from("Reached end of file without finding closing block"),
                 len: 0,
                 index: right_paren.index,
@@ -168,51 +459,314 @@ fn block_statement(
     Ok(stmts)
 }
 
-fn print_statement(
+fn if_statement(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
+) -> Result<ast::Stmt, LoxSyntaxError> {
+    check_depth(tokens, config, depth)?;
+    let if_token = tokens.next().unwrap();
+    let condition = parenthesized_condition(tokens, config, depth, &if_token, "if")?;
+    // A dangling else binds to the nearest if, which is the one still parsing its branch
+    let then_branch = statement(tokens, config, depth)?.into();
+    let else_branch = if matches_any(tokens, vec![TokenKind::Keyword(KeywordKind::Else)]) {
+        tokens.next();
+        Some(statement(tokens, config, depth)?.into())
+    } else {
+        None
+    };
+    Ok(Stmt::If {
+        condition,
+        then_branch,
+        else_branch,
+    })
+}
+
+fn while_statement(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
+) -> Result<ast::Stmt, LoxSyntaxError> {
+    check_depth(tokens, config, depth)?;
+    let while_token = tokens.next().unwrap();
+    let condition = parenthesized_condition(tokens, config, depth, &while_token, "while")?;
+    let body = statement(tokens, config, depth)?.into();
+    Ok(Stmt::While { condition, body })
+}
+
+/// Desugars `for (init; condition; increment) body` into
+/// `{ init; while (condition) { body; increment; } }`, the condition being `true` when omitted
+fn for_statement(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<ast::Stmt, LoxSyntaxError> {
-    let expr = expression(tokens)?;
+    use crate::lexer::TokenKind::*;
+    check_depth(tokens, config, depth)?;
+    let for_token = tokens.next().unwrap();
+    let left_paren = expect(tokens, LeftParen, "Expected '(' after 'for'", &for_token)?;
+
+    let initializer = match tokens.peek() {
+        Some(t) if t.kind == Semicolon => {
+            tokens.next();
+            None
+        }
+        Some(t) if t.kind == Keyword(KeywordKind::Var) => Some(declaration(tokens, config, depth)?),
+        _ => Some(expression_statement(tokens, config, depth)?),
+    };
+
+    let condition = if matches_any(tokens, vec![Semicolon]) {
+        None
+    } else {
+        Some(expression(tokens, config, depth)?)
+    };
+    let semicolon = expect(
+        tokens,
+        Semicolon,
+        "Expected ';' after loop condition",
+        &left_paren,
+    )?;
+
+    let increment = if matches_any(tokens, vec![RightParen]) {
+        None
+    } else {
+        Some(expression(tokens, config, depth)?)
+    };
+    expect(
+        tokens,
+        RightParen,
+        "Expected ')' after for clauses",
+        &semicolon,
+    )?;
+
+    let mut body = statement(tokens, config, depth)?;
+    if let Some(increment) = increment {
+        body = Stmt::Block(vec![body, Stmt::Expression(increment)]);
+    }
+    let condition = condition.unwrap_or(Expr::Literal {
+        value: literal(ast::Literal::True, config),
+        index: for_token.index,
+        len: for_token.len,
+    });
+    let mut desugared = Stmt::While {
+        condition,
+        body: body.into(),
+    };
+    if let Some(initializer) = initializer {
+        desugared = Stmt::Block(vec![initializer, desugared]);
+    }
+    Ok(desugared)
+}
+
+/// Consumes the next token, which has to be of the given kind. Errors point at the token found
+/// instead, or right after `previous` when there are no tokens left.
+fn expect(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    kind: TokenKind,
+    message: &str,
+    previous: &lexer::Token,
+) -> Result<lexer::Token, LoxSyntaxError> {
     match tokens.next() {
-        Some(t) if t.kind == TokenKind::Semicolon => Ok(Stmt::Print(expr)),
-        _ => Err(LoxSyntaxError {
-            message: String::from("Expected ';' after value."),
-            index: expr.index() + expr.len(),
-            len: 0,
+        Some(t) if t.kind == kind => Ok(t),
+        t => {
+            if let Some(error) = t.as_ref().and_then(unexpected_character) {
+                return Err(error);
+            }
+            let (index, len) = t
+                .map(|t| (t.index, t.len))
+                .unwrap_or((previous.index + previous.len, 0));
+            Err(LoxSyntaxError {
+                code: if kind == TokenKind::Semicolon {
+                    "E001"
+                } else {
+                    "E002"
+                },
+                message: String::from(message),
+                index,
+                len,
+            })
+        }
+    }
+}
+
+/// The `( expr )` following `keyword_token`, as in `if` and `while` statements
+fn parenthesized_condition(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
+    keyword_token: &lexer::Token,
+    keyword: &str,
+) -> Result<ast::Expr, LoxSyntaxError> {
+    match tokens.next() {
+        Some(t) if t.kind == TokenKind::LeftParen => {}
+        t => {
+            let (index, len) = t
+                .map(|t| (t.index, t.len))
+                .unwrap_or((keyword_token.index + keyword_token.len, 0));
+            return Err(LoxSyntaxError {
+                code: "E002",
+                message: format!("Expected '(' after '{}'", keyword),
+                index,
+                len,
+            });
+        }
+    }
+    let condition = expression(tokens, config, depth)?;
+    match tokens.next() {
+        Some(t) if t.kind == TokenKind::RightParen => Ok(condition),
+        t => {
+            let (index, len) = t
+                .map(|t| (t.index, t.len))
+                .unwrap_or((condition.index() + condition.len(), 0));
+            Err(LoxSyntaxError {
+                code: "E002",
+                message: format!("Expected ')' after {} condition", keyword),
+                index,
+                len,
+            })
+        }
+    }
+}
+
+fn print_statement(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
+) -> Result<ast::Stmt, LoxSyntaxError> {
+    if let Some(t) = tokens.peek() {
+        if t.kind == TokenKind::Semicolon {
+            return Err(LoxSyntaxError {
+                code: "E003",
+                message: String::from("Expected expression after 'print'"),
+                index: t.index,
+                len: t.len,
+            });
+        }
+    }
+    let expr = expression(tokens, config, depth)?;
+    match statement_end(tokens, config) {
+        Ok(()) => Ok(Stmt::Print(expr)),
+        Err(t) => Err(missing_semicolon(t, &expr)),
+    }
+}
+
+fn return_statement(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
+) -> Result<ast::Stmt, LoxSyntaxError> {
+    let return_token = tokens.next().unwrap();
+    let value = if matches_any(tokens, vec![TokenKind::Semicolon]) {
+        None
+    } else {
+        Some(expression(tokens, config, depth)?)
+    };
+    match (statement_end(tokens, config), value) {
+        (Ok(()), value) => Ok(Stmt::Return {
+            value,
+            index: return_token.index,
+            len: return_token.len,
         }),
+        (Err(t), Some(value)) => Err(missing_semicolon(t, &value)),
+        (Err(t), None) => {
+            let (index, len) = t
+                .map(|t| (t.index, t.len))
+                .unwrap_or((return_token.index + return_token.len, 0));
+            Err(LoxSyntaxError {
+                code: "E001",
+                message: String::from("Expected ';' after return"),
+                index,
+                len,
+            })
+        }
     }
 }
 
 fn expression_statement(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<ast::Stmt, LoxSyntaxError> {
-    let expr = expression(tokens)?;
-    match tokens.next() {
-        Some(t) if t.kind == TokenKind::Semicolon => Ok(Stmt::Expression(expr)),
-        _ => Err(LoxSyntaxError {
-            message: String::from("Expected ';' after value."),
-            index: expr.index() + expr.len(),
-            len: 0,
-        }),
+    let expr = expression(tokens, config, depth)?;
+    match statement_end(tokens, config) {
+        Ok(()) => Ok(Stmt::Expression(expr)),
+        Err(t) => Err(missing_semicolon(t, &expr)),
+    }
+}
+
+/// Takes the `;` ending a statement, or returns whatever took its place. With lenient semicolons
+/// statements may end without one, leaving the next token for the statement after.
+fn statement_end(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+) -> Result<(), Option<lexer::Token>> {
+    match tokens.peek() {
+        Some(t) if t.kind == TokenKind::Semicolon => {
+            tokens.next();
+            Ok(())
+        }
+        _ if config.lenient_semicolons => Ok(()),
+        _ => Err(tokens.next()),
+    }
+}
+
+/// Error for a character no grammar rule uses, found in the place of some other token
+fn unexpected_character(t: &lexer::Token) -> Option<LoxSyntaxError> {
+    let c = match t.kind {
+        TokenKind::Unknown(c) => c,
+        // Only valid before function declarations
+        TokenKind::At => '@',
+        _ => return None,
+    };
+    Some(LoxSyntaxError {
+        code: "E011",
+        message: format!("Unexpected character '{}'", c),
+        index: t.index,
+        len: t.len,
+    })
+}
+
+/// Points at the end of input when the tokens ran out, or right after `expr` otherwise
+fn missing_semicolon(next: Option<lexer::Token>, expr: &Expr) -> LoxSyntaxError {
+    if let Some(error) = next.as_ref().and_then(unexpected_character) {
+        return error;
+    }
+    let (index, len) = match next {
+        Some(t) if t.kind == TokenKind::Eof => (t.index, t.len),
+        _ => (expr.index() + expr.len(), 0),
+    };
+    LoxSyntaxError {
+        code: "E001",
+        message: String::from("Expected ';' after value."),
+        index,
+        len,
     }
 }
 
 fn expression(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<ast::Expr, LoxSyntaxError> {
-    ternary(tokens)
+    check_depth(tokens, config, depth)?;
+    comma(tokens, config, depth)
 }
 
 fn ternary(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<ast::Expr, LoxSyntaxError> {
-    let mut expr = comma(tokens)?;
+    check_depth(tokens, config, depth)?;
+    let mut expr = or(tokens, config, depth)?;
     if let Some(t) = tokens.peek() {
         if t.kind == TokenKind::Interrogation {
             tokens.next();
-            let left = ternary(tokens)?;
-            if let Some(t) = tokens.next() {
-                if t.kind == TokenKind::Colon {
-                    let right = ternary(tokens)?;
+            // Delimited by `?` and `:`, so the middle branch can be any expression
+            let left = expression(tokens, config, depth + 1)?;
+            match tokens.next() {
+                Some(t) if t.kind == TokenKind::Colon => {
+                    let right = ternary(tokens, config, depth + 1)?;
                     let index = expr.index();
                     let len = right.index() + right.len() - index;
                     expr = ast::Expr::Ternary {
@@ -222,21 +776,28 @@ fn ternary(
                         index,
                         len,
                     };
-                } else {
+                }
+                Some(t) if t.kind != TokenKind::Eof => Err(LoxSyntaxError {
+                    code: "E002",
+                    message: String::from(
+                        "Ternary operation missing one branch, expected colon instead",
+                    ),
+                    index: t.index,
+                    len: t.len,
+                })?,
+                t => {
+                    let (index, len) = t
+                        .map(|t| (t.index, t.len))
+                        .unwrap_or((left.index(), left.len()));
                     Err(LoxSyntaxError {
+                        code: "E002",
                         message: String::from(
-                            "Ternary operation missing one branch, expected colon instead",
+                            "Ternary operation missing one branch, expected colon",
                         ),
-                        index: t.index,
-                        len: t.len,
-                    })?;
+                        index,
+                        len,
+                    })?
                 }
-            } else {
-                Err(LoxSyntaxError {
-                    message: String::from("Ternary operation missing one branch, expected colon"),
-                    index: left.index(),
-                    len: left.len(),
-                })?;
             }
         }
     };
@@ -245,11 +806,24 @@ fn ternary(
 
 fn comma(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<ast::Expr, LoxSyntaxError> {
-    let mut expr = assign(tokens)?;
+    let mut expr = assign(tokens, config, depth)?;
+    if !config.comma_operator {
+        return match tokens.peek() {
+            Some(t) if t.kind == TokenKind::Comma => Err(LoxSyntaxError {
+                code: "E002",
+                message: String::from("Unexpected ',' outside of an argument list"),
+                index: t.index,
+                len: t.len,
+            }),
+            _ => Ok(expr),
+        };
+    }
     while matches_any(tokens, vec![lexer::TokenKind::Comma]) {
         let operator: ast::BinOp = tokens.next().unwrap().try_into().unwrap();
-        let right = assign(tokens)?;
+        let right = assign(tokens, config, depth)?;
         let index = expr.index();
         let len = right.index() + right.len() - expr.index();
         expr = ast::Expr::Binary {
@@ -265,57 +839,111 @@ fn comma(
 
 fn assign(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<ast::Expr, LoxSyntaxError> {
+    check_depth(tokens, config, depth)?;
+    // An identifier is only an assignment target when the token after it is `=`
+    let next = peek2(tokens);
+    let name = match (tokens.peek(), next) {
+        (Some(t), Some(next)) if next.kind == TokenKind::Assign => match &t.kind {
+            TokenKind::Identifier(name) => name.clone(),
+            _ => return not_assignment(tokens, config, depth),
+        },
+        _ => return not_assignment(tokens, config, depth),
+    };
+    let ident_token = tokens.next().unwrap();
+    let assign_token = tokens.next().unwrap();
     match tokens.peek() {
-        Some(t) => {
-            let ident_token = t.clone();
-            if let TokenKind::Identifier(name) = ident_token.kind {
-                // We want to look a couple tokens in the iter before confirming this is an asignation,
-                // so we need to make a copy to avoid moving the originals iter cursor
-                let mut tokens_copy = tokens.clone();
-                tokens_copy.next();
-                match tokens_copy.next() {
-                    Some(t) if t.kind == TokenKind::Assign => {
-                        tokens.next();
-                        tokens.next();
-                        match tokens.peek() {
-                            Some(_) => {
-                                let eqexpr = equality(tokens)?;
-                                let len = eqexpr.len();
-                                Ok(Expr::Assign {
-                                    key: name,
-                                    value: Box::new(eqexpr),
-                                    index: ident_token.index,
-                                    len,
-                                })
-                            }
-                            _ => Err(LoxSyntaxError {
-                                message: String::from(
-                                    "Expected expression after asignation identifier",
-                                ),
-                                index: t.index,
-                                len: t.len,
-                            }),
-                        }
-                    }
-                    _ => equality(tokens),
-                }
-            } else {
-                equality(tokens)
-            }
+        Some(_) => {
+            // Right associative, `a = b = 1` assigns 1 to `b` and then to `a`
+            let value = assign(tokens, config, depth + 1)?;
+            let len = value.index() + value.len() - ident_token.index;
+            Ok(Expr::Assign {
+                key: name,
+                value: Box::new(value),
+                index: ident_token.index,
+                len,
+            })
         }
-        _ => equality(tokens),
+        _ => Err(LoxSyntaxError {
+            code: "E003",
+            message: String::from("Expected expression after asignation identifier"),
+            index: assign_token.index,
+            len: assign_token.len,
+        }),
+    }
+}
+
+/// Parses a ternary expression, rejecting it as the target of a following `=` since only
+/// variables can be assigned, not even when wrapped in parentheses like `(x) = 1`
+fn not_assignment(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
+) -> Result<ast::Expr, LoxSyntaxError> {
+    let expr = ternary(tokens, config, depth)?;
+    match tokens.peek() {
+        Some(t) if t.kind == TokenKind::Assign => Err(LoxSyntaxError {
+            code: "E005",
+            message: String::from("Invalid assignment target"),
+            index: expr.index(),
+            len: expr.len(),
+        }),
+        _ => Ok(expr),
+    }
+}
+
+fn or(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
+) -> Result<ast::Expr, LoxSyntaxError> {
+    let mut expr = and(tokens, config, depth)?;
+    while matches_any(tokens, vec![TokenKind::Keyword(KeywordKind::Or)]) {
+        tokens.next();
+        let right = and(tokens, config, depth)?;
+        expr = logical(expr, ast::LogicalOp::Or, right);
+    }
+    Ok(expr)
+}
+
+fn and(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
+) -> Result<ast::Expr, LoxSyntaxError> {
+    let mut expr = equality(tokens, config, depth)?;
+    while matches_any(tokens, vec![TokenKind::Keyword(KeywordKind::And)]) {
+        tokens.next();
+        let right = equality(tokens, config, depth)?;
+        expr = logical(expr, ast::LogicalOp::And, right);
+    }
+    Ok(expr)
+}
+
+fn logical(left: Expr, operator: ast::LogicalOp, right: Expr) -> Expr {
+    let index = left.index();
+    let len = right.index() + right.len() - index;
+    Expr::Logical {
+        left: left.into(),
+        operator,
+        right: right.into(),
+        index,
+        len,
     }
 }
 
 fn equality(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<ast::Expr, LoxSyntaxError> {
     use crate::lexer::TokenKind::*;
-    let mut expr = comparison(tokens)?;
+    let mut expr = comparison(tokens, config, depth)?;
     while matches_any(tokens, vec![NotEquals, Equals]) {
         let operator: ast::BinOp = tokens.next().unwrap().try_into().unwrap();
-        let right: ast::Expr = comparison(tokens)?;
+        let right: ast::Expr = comparison(tokens, config, depth)?;
         let index = expr.index();
         let len = right.index() + right.len() - expr.index();
         expr = ast::Expr::Binary {
@@ -331,15 +959,17 @@ fn equality(
 
 fn comparison(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<ast::Expr, LoxSyntaxError> {
     use crate::lexer::TokenKind::*;
-    let mut expr = term(tokens)?;
+    let mut expr = term(tokens, config, depth)?;
     while matches_any(
         tokens,
         vec![GreaterThan, GreaterThanEquals, LessThan, LessThanEquals],
     ) {
         let operator: ast::BinOp = tokens.next().unwrap().try_into().unwrap();
-        let right: ast::Expr = term(tokens)?;
+        let right: ast::Expr = term(tokens, config, depth)?;
         let index = expr.index();
         let len = right.index() + right.len() - expr.index();
         expr = ast::Expr::Binary {
@@ -355,12 +985,14 @@ fn comparison(
 
 fn term(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<ast::Expr, LoxSyntaxError> {
     use crate::lexer::TokenKind::*;
-    let mut expr = factor(tokens)?;
+    let mut expr = factor(tokens, config, depth)?;
     while matches_any(tokens, vec![Minus, Plus]) {
         let operator: ast::BinOp = tokens.next().unwrap().try_into().unwrap();
-        let right: ast::Expr = factor(tokens)?;
+        let right: ast::Expr = factor(tokens, config, depth)?;
         let index = expr.index();
         let len = right.index() + right.len() - expr.index();
         expr = ast::Expr::Binary {
@@ -376,12 +1008,14 @@ fn term(
 
 fn factor(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<ast::Expr, LoxSyntaxError> {
     use crate::lexer::TokenKind::*;
-    let mut expr = unary(tokens)?;
+    let mut expr = unary(tokens, config, depth)?;
     while matches_any(tokens, vec![Slash, Star]) {
         let operator: ast::BinOp = tokens.next().unwrap().try_into().unwrap();
-        let right: ast::Expr = unary(tokens)?;
+        let right: ast::Expr = unary(tokens, config, depth)?;
         let index = expr.index();
         let len = right.index() + right.len() - expr.index();
         expr = ast::Expr::Binary {
@@ -397,13 +1031,16 @@ fn factor(
 
 fn unary(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<ast::Expr, LoxSyntaxError> {
     use crate::lexer::TokenKind::*;
+    check_depth(tokens, config, depth)?;
     if matches_any(tokens, vec![Bang, Minus]) {
         let op_token = tokens.next().unwrap();
         let index = op_token.index;
-        let operator: ast::UnaryOp = op_token.try_into().unwrap();
-        let right = unary(tokens)?;
+        let operator = unary_operator(op_token)?;
+        let right = unary(tokens, config, depth + 1)?;
         let len = right.index() + right.len() - index;
 
         Ok(ast::Expr::Unary {
@@ -413,81 +1050,288 @@ fn unary(
             len,
         })
     } else {
-        primary(tokens)
+        postfix(tokens, config, depth)
     }
 }
 
-fn primary(
+fn unary_operator(token: lexer::Token) -> Result<ast::UnaryOp, LoxSyntaxError> {
+    let (index, len) = (token.index, token.len);
+    token.try_into().map_err(|message| LoxSyntaxError {
+        code: "E003",
+        message,
+        index,
+        len,
+    })
+}
+
+fn postfix(
     tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
 ) -> Result<ast::Expr, LoxSyntaxError> {
-    use crate::lexer::{KeywordKind::*, LiteralKind::*, TokenKind::*};
-    if let Some(t) = tokens.next() {
-        let expr = match t.kind {
-            Keyword(True) => ast::Expr::Literal {
-                value: ast::Literal::True,
-                index: t.index,
-                len: t.len,
-            },
-            Keyword(False) => ast::Expr::Literal {
-                value: ast::Literal::False,
-                index: t.index,
-                len: t.len,
-            },
-            Keyword(Nil) => ast::Expr::Literal {
-                value: ast::Literal::Nil,
-                index: t.index,
-                len: t.len,
-            },
-            Identifier(value) => ast::Expr::Variable {
-                value,
-                index: t.index,
-                len: t.len,
-            },
-            Literal(k) => match k {
-                Number(n) => ast::Expr::Literal {
-                    value: ast::Literal::Number(n),
-                    index: t.index,
-                    len: t.len,
-                },
-                Str {
-                    terminated: _,
-                    value,
-                } => ast::Expr::Literal {
-                    value: ast::Literal::Str(value),
-                    index: t.index,
-                    len: t.len,
-                },
-            },
-            LeftParen => {
-                let expr = expression(tokens)?;
-                if let Some(t) = tokens.next() {
-                    if t.kind == RightParen {
-                        let index = expr.index();
-                        let len = expr.len();
-                        ast::Expr::Grouping {
-                            expr: expr.into(),
-                            index,
-                            len,
-                        }
-                    } else {
-                        Err(LoxSyntaxError {
-                            message: format!(
-                                "The token {:?} was not expected, a ')' was expected",
-                                t.kind,
-                            ),
-                            index: t.index,
-                            len: t.len,
-                        })?
-                    }
-                } else {
-                    Err(LoxSyntaxError {
-                        message: String::from("Expected ')' after grouped expression"),
-                        index: expr.index(),
-                        len: expr.len(),
-                    })?
+    let mut expr = primary(tokens, config, depth)?;
+    loop {
+        expr = match tokens.peek().map(|t| &t.kind) {
+            Some(TokenKind::Bang) => {
+                let bang = tokens.next().unwrap();
+                let index = expr.index();
+                let len = bang.index + bang.len - index;
+                ast::Expr::Unary {
+                    operator: ast::UnaryOp::AssertNotNil,
+                    right: expr.into(),
+                    index,
+                    len,
                 }
             }
+            Some(TokenKind::LeftParen) => call(tokens, config, depth, expr)?,
+            Some(TokenKind::Dot) => return Err(property(tokens)),
+            _ => return Ok(expr),
+        };
+    }
+}
+
+/// Error for the `.name` following an expression, since no Lox value has properties yet
+fn property(tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>) -> LoxSyntaxError {
+    let dot = tokens.next().unwrap();
+    match identifier(tokens, "Expected property name after '.'", &dot) {
+        Ok((name, t)) => LoxSyntaxError {
+            code: "E002",
+            message: format!(
+                "Can't access property '{}', values have no properties",
+                name
+            ),
+            index: dot.index,
+            len: t.index + t.len - dot.index,
+        },
+        Err(e) => e,
+    }
+}
+
+/// Most arguments a call can pass, as in reference Lox
+const MAX_ARGUMENTS: usize = 255;
+
+/// Parses the parenthesized arguments passed to `callee`
+fn call(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
+    callee: Expr,
+) -> Result<ast::Expr, LoxSyntaxError> {
+    let left_paren = tokens.next().unwrap();
+    let mut arguments = Vec::new();
+    if !matches_any(tokens, vec![TokenKind::RightParen]) {
+        loop {
+            let argument = assign(tokens, config, depth + 1)?;
+            if arguments.len() == MAX_ARGUMENTS {
+                return Err(LoxSyntaxError {
+                    code: "E006",
+                    message: format!("Can't pass more than {} arguments", MAX_ARGUMENTS),
+                    index: argument.index(),
+                    len: argument.len(),
+                });
+            }
+            arguments.push(argument);
+            if !matches_any(tokens, vec![TokenKind::Comma]) {
+                break;
+            }
+            tokens.next();
+        }
+    }
+    let right_paren = expect(
+        tokens,
+        TokenKind::RightParen,
+        "Expected ')' after arguments",
+        &left_paren,
+    )?;
+    let index = callee.index();
+    Ok(Expr::Call {
+        callee: callee.into(),
+        arguments: arguments.into(),
+        index,
+        len: right_paren.index + right_paren.len - index,
+    })
+}
+
+fn primary(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
+) -> Result<ast::Expr, LoxSyntaxError> {
+    // Only groupings nest, keeping the rest out of the way saves stack on every nesting level
+    match tokens.peek() {
+        Some(t) if t.kind == TokenKind::LeftParen => grouping(tokens, config, depth),
+        _ => atom(tokens, config),
+    }
+}
+
+/// `( expr )`
+fn grouping(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+    depth: usize,
+) -> Result<ast::Expr, LoxSyntaxError> {
+    let left_paren = tokens.next().unwrap();
+    let expr = expression(tokens, config, depth + 1)?;
+    match tokens.next() {
+        Some(closing) if closing.kind == TokenKind::RightParen => {
+            // The span covers the parentheses so enclosing expressions span them too
+            let index = left_paren.index;
+            let len = closing.index + closing.len - index;
+            Ok(ast::Expr::Grouping {
+                expr: expr.into(),
+                index,
+                len,
+            })
+        }
+        Some(closing) if closing.kind != TokenKind::Eof => Err(unexpected_character(&closing)
+            .unwrap_or_else(|| LoxSyntaxError {
+                code: "E002",
+                message: format!(
+                    "The token {:?} was not expected, a ')' was expected",
+                    closing.kind,
+                ),
+                index: closing.index,
+                len: closing.len,
+            })),
+        eof => {
+            let (index, len) = eof
+                .map(|t| (t.index, t.len))
+                .unwrap_or((expr.index(), expr.len()));
+            Err(LoxSyntaxError {
+                code: "E002",
+                message: String::from("Expected ')' after grouped expression"),
+                index,
+                len,
+            })
+        }
+    }
+}
+
+/// Largest whole number whose literals are shared with `Config::intern_literals`
+const MAX_INTERNED_NUMBER: usize = 255;
+
+/// The literal nodes shared by `Config::intern_literals`, the same ones for every program parsed
+/// on a thread
+struct Interned {
+    true_value: Rc<ast::Literal>,
+    false_value: Rc<ast::Literal>,
+    nil: Rc<ast::Literal>,
+    numbers: Vec<Rc<ast::Literal>>,
+}
+
+thread_local! {
+    static INTERNED: Interned = Interned {
+        true_value: Rc::new(ast::Literal::True),
+        false_value: Rc::new(ast::Literal::False),
+        nil: Rc::new(ast::Literal::Nil),
+        numbers: (0..=MAX_INTERNED_NUMBER)
+            .map(|n| Rc::new(ast::Literal::Number(n as f64)))
+            .collect(),
+    };
+}
+
+/// The node of a literal, shared with every other literal of the same value when
+/// `config.intern_literals` is on and the value is one of the interned ones
+fn literal(value: ast::Literal, config: &Config) -> Rc<ast::Literal> {
+    if !config.intern_literals {
+        return Rc::new(value);
+    }
+    let interned = INTERNED.with(|interned| match value {
+        ast::Literal::True => Some(interned.true_value.clone()),
+        ast::Literal::False => Some(interned.false_value.clone()),
+        ast::Literal::Nil => Some(interned.nil.clone()),
+        // Literals are never negative, `-1` is a negation
+        ast::Literal::Number(n) if n.fract() == 0.0 && n <= MAX_INTERNED_NUMBER as f64 => {
+            interned.numbers.get(n as usize).cloned()
+        }
+        _ => None,
+    });
+    interned.unwrap_or_else(|| Rc::new(value))
+}
+
+/// Literals and variables, the leaves of expressions
+fn atom(
+    tokens: &mut Peekable<impl Iterator<Item = lexer::Token> + Clone>,
+    config: &Config,
+) -> Result<ast::Expr, LoxSyntaxError> {
+    use crate::lexer::{KeywordKind::*, LiteralKind::*, TokenKind::*};
+    if let Some(t) = tokens.next() {
+        let expr = match t.kind {
+            Keyword(True) => ast::Expr::Literal {
+                value: literal(ast::Literal::True, config),
+                index: t.index,
+                len: t.len,
+            },
+            Keyword(False) => ast::Expr::Literal {
+                value: literal(ast::Literal::False, config),
+                index: t.index,
+                len: t.len,
+            },
+            Keyword(Nil) => ast::Expr::Literal {
+                value: literal(ast::Literal::Nil, config),
+                index: t.index,
+                len: t.len,
+            },
+            Identifier(value) => ast::Expr::Variable {
+                value,
+                index: t.index,
+                len: t.len,
+            },
+            // Statements starting with `print` are print statements, anywhere else it names the
+            // native function, which returns what it prints
+            Keyword(Print) => ast::Expr::Variable {
+                value: String::from("print"),
+                index: t.index,
+                len: t.len,
+            },
+            Literal(k) => match k {
+                Number { value, .. } => ast::Expr::Literal {
+                    value: literal(ast::Literal::Number(value), config),
+                    index: t.index,
+                    len: t.len,
+                },
+                Str {
+                    terminated: _,
+                    value,
+                } => ast::Expr::Literal {
+                    value: Rc::new(ast::Literal::Str(value)),
+                    index: t.index,
+                    len: t.len,
+                },
+                MalformedNumber(lexeme) => Err(LoxSyntaxError {
+                    code: "E008",
+                    message: format!("Malformed number literal \"{}\"", lexeme),
+                    index: t.index,
+                    len: t.len,
+                })?,
+                MalformedString(lexeme) => Err(LoxSyntaxError {
+                    code: "E010",
+                    message: format!("Invalid escape sequence in string {}", lexeme),
+                    index: t.index,
+                    len: t.len,
+                })?,
+            },
+            Eof => Err(LoxSyntaxError {
+                code: "E003",
+                message: String::from("Expected expression but reached the end of input"),
+                index: t.index,
+                len: t.len,
+            })?,
+            Unknown(_) | At => Err(unexpected_character(&t).unwrap())?,
+            Dot => Err(LoxSyntaxError {
+                code: "E003",
+                message: String::from("Unexpected '.'"),
+                index: t.index,
+                len: t.len,
+            })?,
+            Assign => Err(LoxSyntaxError {
+                code: "E003",
+                message: String::from("Expected expression before '='"),
+                index: t.index,
+                len: t.len,
+            })?,
             tk => Err(LoxSyntaxError {
+                code: "E003",
                 message: format!("Token \"{:?}\" does not match a valid expression", tk),
                 index: t.index,
                 len: t.len,
@@ -497,6 +1341,7 @@ fn primary(
     } else {
         // TODO: This should be captured and managed acordingly, the index and len are invalid (maybe a different type of error?)
         Err(LoxSyntaxError {
+            code: "E003",
             message: String::from("The expression is does not have a leaf node"),
             index: 0,
             len: 0,
@@ -504,6 +1349,37 @@ fn primary(
     }
 }
 
+/// Bails out of expressions nested deeper than the configured limit before they can exhaust the
+/// stack of this recursive descent parser
+fn check_depth<P: Iterator<Item = lexer::Token> + Clone>(
+    tokens: &mut Peekable<P>,
+    config: &Config,
+    depth: usize,
+) -> Result<(), LoxSyntaxError> {
+    if depth <= config.max_depth {
+        return Ok(());
+    }
+    let (index, len) = tokens.peek().map(|t| (t.index, t.len)).unwrap_or((0, 0));
+    Err(LoxSyntaxError {
+        code: "E009",
+        message: String::from("Expression too deeply nested"),
+        index,
+        len,
+    })
+}
+
+/// Whether every token was consumed, either because the iterator ran out or it reached `Eof`
+fn at_end<P: Iterator<Item = lexer::Token> + Clone>(tokens: &mut Peekable<P>) -> bool {
+    !matches!(tokens.peek(), Some(t) if t.kind != TokenKind::Eof)
+}
+
+/// The token after the next one, looked up on a copy of the iterator so nothing is consumed
+fn peek2<P: Iterator<Item = lexer::Token> + Clone>(tokens: &Peekable<P>) -> Option<lexer::Token> {
+    let mut tokens = tokens.clone();
+    tokens.next();
+    tokens.next()
+}
+
 fn matches_any<P: Iterator<Item = lexer::Token> + Clone>(
     tokens: &Peekable<P>,
     to_match: Vec<crate::lexer::TokenKind>,
@@ -522,27 +1398,56 @@ fn matches_any<P: Iterator<Item = lexer::Token> + Clone>(
 
 #[cfg(test)]
 mod tests {
-    use super::expression;
-    use crate::ast::{BinOp::*, Expr::*, Literal::*};
-    use crate::lexer::{tokenize, TokenKind};
+    use super::{expression, parse, parse_expression, peek2, unary_operator, LoxSyntaxError};
+    use crate::ast::{BinOp::*, Expr, Expr::*, Literal::*, LogicalOp::*, Stmt, UnaryOp::*};
+    use crate::config::Config;
+    use crate::lexer::{tokenize, tokenize_with_eof, Token, TokenKind};
+    use std::rc::Rc;
 
-    #[test]
-    fn parse_comma_operator() {
-        let mut tokens = tokenize("1,2,3")
+    fn parse_code(code: &str) -> Result<Vec<Stmt>, Vec<LoxSyntaxError>> {
+        let mut tokens = tokenize_with_eof(code)
             .filter(|t| t.kind != TokenKind::Whitespace)
             .peekable();
-        let ast = expression(&mut tokens).unwrap();
+        parse(&mut tokens, &Config::default())
+    }
+
+    /// The first error `code` fails to parse with
+    fn parse_error(code: &str) -> LoxSyntaxError {
+        parse_code(code).unwrap_err().remove(0)
+    }
+
+    fn parse_expr(code: &str) -> Result<Expr, LoxSyntaxError> {
+        parse_expr_with(code, &Config::default())
+    }
+
+    fn parse_expr_with(code: &str, config: &Config) -> Result<Expr, LoxSyntaxError> {
+        let mut tokens = tokenize_with_eof(code)
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .peekable();
+        parse_expression(&mut tokens, config)
+    }
+
+    fn comma_operator() -> Config {
+        Config {
+            comma_operator: true,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn parse_comma_operator() {
+        let ast = parse_expr_with("1,2,3", &comma_operator()).unwrap();
         let expected = Binary {
             left: Binary {
                 left: Literal {
-                    value: Number(1.0),
+                    value: Number(1.0).into(),
                     index: 0,
                     len: 1,
                 }
                 .into(),
                 operator: Comma,
                 right: Literal {
-                    value: Number(2.0),
+                    value: Number(2.0).into(),
                     index: 2,
                     len: 1,
                 }
@@ -553,7 +1458,7 @@ mod tests {
             .into(),
             operator: Comma,
             right: Literal {
-                value: Number(3.0),
+                value: Number(3.0).into(),
                 index: 4,
                 len: 1,
             }
@@ -568,26 +1473,22 @@ mod tests {
     #[test]
     fn parse_ternary_expression() {
         // simple
-        let mut tokens = tokenize("true ? 1 : 2")
-            .filter(|t| t.kind != TokenKind::Whitespace)
-            .peekable();
-        // println!("{:?}", tokens.clone().collect::<Vec<crate::lexer::Token>>());
-        let ast = expression(&mut tokens).unwrap();
+        let ast = parse_expr("true ? 1 : 2").unwrap();
         let expected = Ternary {
             condition: Literal {
-                value: True,
+                value: True.into(),
                 index: 0,
                 len: 4,
             }
             .into(),
             left: Literal {
-                value: Number(1.0),
+                value: Number(1.0).into(),
                 index: 7,
                 len: 1,
             }
             .into(),
             right: Literal {
-                value: Number(2.0),
+                value: Number(2.0).into(),
                 index: 11,
                 len: 1,
             }
@@ -599,21 +1500,18 @@ mod tests {
         assert_eq!(ast, expected);
 
         // eq on condition
-        let mut tokens = tokenize("1 == 2 ? 1 : 2")
-            .filter(|t| t.kind != TokenKind::Whitespace)
-            .peekable();
-        let ast = expression(&mut tokens).unwrap();
+        let ast = parse_expr("1 == 2 ? 1 : 2").unwrap();
         let expected = Ternary {
             condition: Binary {
                 left: Literal {
-                    value: Number(1.0),
+                    value: Number(1.0).into(),
                     index: 0,
                     len: 1,
                 }
                 .into(),
-                operator: Equals.into(),
+                operator: Equals,
                 right: Literal {
-                    value: Number(2.0),
+                    value: Number(2.0).into(),
                     index: 5,
                     len: 1,
                 }
@@ -623,13 +1521,13 @@ mod tests {
             }
             .into(),
             left: Literal {
-                value: Number(1.0),
+                value: Number(1.0).into(),
                 index: 9,
                 len: 1,
             }
             .into(),
             right: Literal {
-                value: Number(2.0),
+                value: Number(2.0).into(),
                 index: 13,
                 len: 1,
             }
@@ -641,27 +1539,24 @@ mod tests {
         assert_eq!(ast, expected);
 
         // binary op on branches
-        let mut tokens = tokenize("true ? 1 - 2 : 1 + 2")
-            .filter(|t| t.kind != TokenKind::Whitespace)
-            .peekable();
-        let ast = expression(&mut tokens).unwrap();
+        let ast = parse_expr("true ? 1 - 2 : 1 + 2").unwrap();
         let expected = Ternary {
             condition: Literal {
-                value: True,
+                value: True.into(),
                 index: 0,
                 len: 4,
             }
             .into(),
             left: Binary {
                 left: Literal {
-                    value: Number(1.0),
+                    value: Number(1.0).into(),
                     index: 7,
                     len: 1,
                 }
                 .into(),
-                operator: Substraction.into(),
+                operator: Substraction,
                 right: Literal {
-                    value: Number(2.0),
+                    value: Number(2.0).into(),
                     index: 11,
                     len: 1,
                 }
@@ -672,14 +1567,14 @@ mod tests {
             .into(),
             right: Binary {
                 left: Literal {
-                    value: Number(1.0),
+                    value: Number(1.0).into(),
                     index: 15,
                     len: 1,
                 }
                 .into(),
-                operator: Sum.into(),
+                operator: Sum,
                 right: Literal {
-                    value: Number(2.0),
+                    value: Number(2.0).into(),
                     index: 19,
                     len: 1,
                 }
@@ -695,38 +1590,35 @@ mod tests {
         assert_eq!(ast, expected);
 
         // nested right
-        let mut tokens = tokenize("true ? 1 : 2 ? 3 : 4")
-            .filter(|t| t.kind != TokenKind::Whitespace)
-            .peekable();
-        let ast = expression(&mut tokens).unwrap();
+        let ast = parse_expr("true ? 1 : 2 ? 3 : 4").unwrap();
         let expected = Ternary {
             condition: Literal {
-                value: True,
+                value: True.into(),
                 index: 0,
                 len: 4,
             }
             .into(),
             left: Literal {
-                value: Number(1.0),
+                value: Number(1.0).into(),
                 index: 7,
                 len: 1,
             }
             .into(),
             right: Ternary {
                 condition: Literal {
-                    value: Number(2.0),
+                    value: Number(2.0).into(),
                     index: 11,
                     len: 1,
                 }
                 .into(),
                 left: Literal {
-                    value: Number(3.0),
+                    value: Number(3.0).into(),
                     index: 15,
                     len: 1,
                 }
                 .into(),
                 right: Literal {
-                    value: Number(4.0),
+                    value: Number(4.0).into(),
                     index: 19,
                     len: 1,
                 }
@@ -742,32 +1634,29 @@ mod tests {
         assert_eq!(ast, expected);
 
         // nested left
-        let mut tokens = tokenize("true ? 1 ? 2 : 3 : 4")
-            .filter(|t| t.kind != TokenKind::Whitespace)
-            .peekable();
-        let ast = expression(&mut tokens).unwrap();
+        let ast = parse_expr("true ? 1 ? 2 : 3 : 4").unwrap();
         let expected = Ternary {
             condition: Literal {
-                value: True,
+                value: True.into(),
                 index: 0,
                 len: 4,
             }
             .into(),
             left: Ternary {
                 condition: Literal {
-                    value: Number(1.0),
+                    value: Number(1.0).into(),
                     index: 7,
                     len: 1,
                 }
                 .into(),
                 left: Literal {
-                    value: Number(2.0),
+                    value: Number(2.0).into(),
                     index: 11,
                     len: 1,
                 }
                 .into(),
                 right: Literal {
-                    value: Number(3.0),
+                    value: Number(3.0).into(),
                     index: 15,
                     len: 1,
                 }
@@ -777,7 +1666,7 @@ mod tests {
             }
             .into(),
             right: Literal {
-                value: Number(4.0),
+                value: Number(4.0).into(),
                 index: 19,
                 len: 1,
             }
@@ -788,4 +1677,881 @@ mod tests {
 
         assert_eq!(ast, expected);
     }
+
+    #[test]
+    fn parse_without_comma_operator() {
+        let err = parse_error("1, 2;");
+        assert_eq!(err.message, "Unexpected ',' outside of an argument list");
+        assert_eq!((err.index, err.len), (1, 1));
+
+        let mut tokens = tokenize("1, 2;")
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .peekable();
+        assert!(parse(&mut tokens, &comma_operator()).is_ok());
+    }
+
+    #[test]
+    fn parse_unary_spans() {
+        let ast = parse_expr("!!true").unwrap();
+        let expected = Unary {
+            operator: LogicNegate,
+            right: Unary {
+                operator: LogicNegate,
+                right: Literal {
+                    value: True.into(),
+                    index: 2,
+                    len: 4,
+                }
+                .into(),
+                index: 1,
+                len: 5,
+            }
+            .into(),
+            index: 0,
+            len: 6,
+        };
+        assert_eq!(ast, expected);
+
+        // Double negation spans both operators, whitespace included
+        let ast = parse_expr("- -5").unwrap();
+        match &ast {
+            Unary { right, .. } => assert_eq!((right.index(), right.len()), (2, 2)),
+            e => panic!("Expected a unary expression but got {:?}", e),
+        }
+        assert_eq!((ast.index(), ast.len()), (0, 4));
+
+        // Parentheses around the operand are part of the span
+        let ast = parse_expr("- ( 1 )").unwrap();
+        assert_eq!((ast.index(), ast.len()), (0, 7));
+    }
+
+    #[test]
+    fn parse_print_without_expression() {
+        let err = parse_error("print;");
+        assert_eq!(err.message, "Expected expression after 'print'");
+        assert_eq!((err.index, err.len), (5, 1));
+    }
+
+    #[test]
+    fn parse_assignment_precedence() {
+        // Assignment is right associative
+        let expected = Assign {
+            key: String::from("a"),
+            value: Assign {
+                key: String::from("b"),
+                value: Literal {
+                    value: Number(1.0).into(),
+                    index: 8,
+                    len: 1,
+                }
+                .into(),
+                index: 4,
+                len: 5,
+            }
+            .into(),
+            index: 0,
+            len: 9,
+        };
+        assert_eq!(parse_expr("a = b = 1").unwrap(), expected);
+
+        // The comma binds looser than assignment
+        let expected = Binary {
+            left: Literal {
+                value: Number(1.0).into(),
+                index: 0,
+                len: 1,
+            }
+            .into(),
+            operator: Comma,
+            right: Assign {
+                key: String::from("a"),
+                value: Literal {
+                    value: Number(2.0).into(),
+                    index: 7,
+                    len: 1,
+                }
+                .into(),
+                index: 3,
+                len: 5,
+            }
+            .into(),
+            index: 0,
+            len: 8,
+        };
+        assert_eq!(
+            parse_expr_with("1, a = 2", &comma_operator()).unwrap(),
+            expected
+        );
+
+        // While the ternary binds tighter, with any expression between `?` and `:`
+        let expected = Assign {
+            key: String::from("a"),
+            value: Ternary {
+                condition: Variable {
+                    value: String::from("c"),
+                    index: 4,
+                    len: 1,
+                }
+                .into(),
+                left: Binary {
+                    left: Literal {
+                        value: Number(1.0).into(),
+                        index: 8,
+                        len: 1,
+                    }
+                    .into(),
+                    operator: Comma,
+                    right: Literal {
+                        value: Number(2.0).into(),
+                        index: 11,
+                        len: 1,
+                    }
+                    .into(),
+                    index: 8,
+                    len: 4,
+                }
+                .into(),
+                right: Literal {
+                    value: Number(3.0).into(),
+                    index: 15,
+                    len: 1,
+                }
+                .into(),
+                index: 4,
+                len: 12,
+            }
+            .into(),
+            index: 0,
+            len: 16,
+        };
+        assert_eq!(
+            parse_expr_with("a = c ? 1, 2 : 3", &comma_operator()).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn parse_leading_assign() {
+        let err = parse_error("= 5;");
+        assert_eq!(err.message, "Expected expression before '='");
+        assert_eq!((err.index, err.len), (0, 1));
+    }
+
+    #[test]
+    fn parse_errors_at_eof() {
+        let describe = |code| {
+            let err = parse_error(code);
+            (err.message, err.index, err.len)
+        };
+        assert_eq!(
+            describe("1 + "),
+            (
+                String::from("Expected expression but reached the end of input"),
+                4,
+                0
+            )
+        );
+        assert_eq!(
+            describe("print (1 "),
+            (String::from("Expected ')' after grouped expression"), 9, 0)
+        );
+        assert_eq!(
+            describe("1 + 2 "),
+            (String::from("Expected ';' after value."), 6, 0)
+        );
+        assert_eq!(
+            describe("var a = 1"),
+            (
+                String::from("Expected ';' after variable declaration"),
+                9,
+                0
+            )
+        );
+        assert_eq!(
+            describe("{ 1; "),
+            (
+                String::from("Reached end of file without finding closing block"),
+                0,
+                0
+            )
+        );
+
+        assert_eq!(parse_code("1; 2;").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn render_error_line_and_column() {
+        let code = "var été = 1;\n  été + ;";
+        let err = parse_error(code);
+        assert_eq!(
+            err.render(code),
+            "Error[E003]: Token \"Semicolon\" does not match a valid expression at 2:9"
+        );
+    }
+
+    #[test]
+    fn parse_assignment_lookahead() {
+        let mut tokens = tokenize("a = 1")
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .peekable();
+        assert_eq!(peek2(&tokens).map(|t| t.kind), Some(TokenKind::Assign));
+        let ast = expression(&mut tokens, &Config::default(), 0).unwrap();
+        assert!(matches!(ast, Assign { ref key, .. } if key == "a"));
+
+        // Same first token, but the second one makes it a comparison
+        let ast = parse_expr("a == 1").unwrap();
+        assert!(matches!(
+            ast,
+            Binary {
+                operator: Equals,
+                ..
+            }
+        ));
+
+        let mut tokens = tokenize("a").peekable();
+        assert!(peek2(&tokens).is_none());
+        assert!(expression(&mut tokens, &Config::default(), 0).is_ok());
+    }
+
+    #[test]
+    fn parse_variable_reference() {
+        let ast = parse_code("greeting;").unwrap();
+        assert_eq!(
+            ast,
+            vec![Stmt::Expression(Variable {
+                value: String::from("greeting"),
+                index: 0,
+                len: 8,
+            })]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_assignment_target() {
+        for (code, span) in [
+            ("(x) = 1", (0, 3)),
+            ("a + b = 1", (0, 5)),
+            ("f() = 1", (0, 3)),
+        ] {
+            let err = parse_expr(code).unwrap_err();
+            assert_eq!(err.message, "Invalid assignment target");
+            assert_eq!((err.index, err.len), span);
+        }
+    }
+
+    #[test]
+    fn parse_variable_declaration() {
+        assert_eq!(
+            parse_code("var x = 1; var y;").unwrap(),
+            vec![
+                Stmt::Variable {
+                    name: String::from("x"),
+                    initializer: Some(Literal {
+                        value: Number(1.0).into(),
+                        index: 8,
+                        len: 1,
+                    }),
+                    index: 4,
+                    len: 1,
+                },
+                Stmt::Variable {
+                    name: String::from("y"),
+                    initializer: None,
+                    index: 15,
+                    len: 1,
+                },
+            ]
+        );
+
+        let err = parse_error("var = 1;");
+        assert_eq!(err.message, "Expected variable name but found Assign");
+        assert_eq!((err.index, err.len), (4, 1));
+        let err = parse_error("var x = 1 print x;");
+        assert_eq!(err.message, "Expected ';' after variable declaration");
+        assert_eq!((err.index, err.len), (10, 5));
+        let err = parse_error("var x print x;");
+        assert_eq!((err.index, err.len), (6, 5));
+    }
+
+    #[test]
+    fn parse_nested_blocks() {
+        let ast = parse_code("{ var a = 1; { var a = 2; print a; } print a; }").unwrap();
+        let expected = vec![Stmt::Block(vec![
+            Stmt::Variable {
+                name: String::from("a"),
+                initializer: Some(Literal {
+                    value: Number(1.0).into(),
+                    index: 10,
+                    len: 1,
+                }),
+                index: 6,
+                len: 1,
+            },
+            Stmt::Block(vec![
+                Stmt::Variable {
+                    name: String::from("a"),
+                    initializer: Some(Literal {
+                        value: Number(2.0).into(),
+                        index: 23,
+                        len: 1,
+                    }),
+                    index: 19,
+                    len: 1,
+                },
+                Stmt::Print(Variable {
+                    value: String::from("a"),
+                    index: 32,
+                    len: 1,
+                }),
+            ]),
+            Stmt::Print(Variable {
+                value: String::from("a"),
+                index: 43,
+                len: 1,
+            }),
+        ])];
+        assert_eq!(ast, expected);
+
+        let err = parse_error("{ { print 1; }");
+        assert_eq!(
+            err.message,
+            "Reached end of file without finding closing block"
+        );
+        assert_eq!((err.index, err.len), (0, 0));
+    }
+
+    #[test]
+    fn parse_dangling_else() {
+        let ast = parse_code("if (a) if (b) print 1; else print 2;").unwrap();
+        let print = |n: f64, index| {
+            Stmt::Print(Literal {
+                value: Number(n).into(),
+                index,
+                len: 1,
+            })
+            .into()
+        };
+        let expected = vec![Stmt::If {
+            condition: Variable {
+                value: String::from("a"),
+                index: 4,
+                len: 1,
+            },
+            then_branch: Stmt::If {
+                condition: Variable {
+                    value: String::from("b"),
+                    index: 11,
+                    len: 1,
+                },
+                then_branch: print(1.0, 20),
+                else_branch: Some(print(2.0, 34)),
+            }
+            .into(),
+            else_branch: None,
+        }];
+        assert_eq!(ast, expected);
+
+        let err = parse_error("if true print 1;");
+        assert_eq!(err.message, "Expected '(' after 'if'");
+        assert_eq!((err.index, err.len), (3, 4));
+    }
+
+    #[test]
+    fn parse_while_statement() {
+        let ast = parse_code("while (a) a = false;").unwrap();
+        assert!(matches!(
+            &ast[..],
+            [Stmt::While {
+                condition: Variable { .. },
+                ..
+            }]
+        ));
+
+        let err = parse_code("while (a print a;").unwrap_err().remove(0);
+        assert_eq!(err.message, "Expected ')' after while condition");
+        assert_eq!((err.index, err.len), (9, 5));
+    }
+
+    #[test]
+    fn parse_for_desugaring() {
+        let variable = |index| Variable {
+            value: String::from("i"),
+            index,
+            len: 1,
+        };
+        let number = |n: f64, index| Literal {
+            value: Number(n).into(),
+            index,
+            len: 1,
+        };
+
+        let ast = parse_code("for (var i = 0; i < 2; i = i + 1) print i;").unwrap();
+        let expected = vec![Stmt::Block(vec![
+            Stmt::Variable {
+                name: String::from("i"),
+                initializer: Some(number(0.0, 13)),
+                index: 9,
+                len: 1,
+            },
+            Stmt::While {
+                condition: Binary {
+                    left: variable(16).into(),
+                    operator: LessThan,
+                    right: number(2.0, 20).into(),
+                    index: 16,
+                    len: 5,
+                },
+                body: Stmt::Block(vec![
+                    Stmt::Print(variable(40)),
+                    Stmt::Expression(Assign {
+                        key: String::from("i"),
+                        value: Binary {
+                            left: variable(27).into(),
+                            operator: Sum,
+                            right: number(1.0, 31).into(),
+                            index: 27,
+                            len: 5,
+                        }
+                        .into(),
+                        index: 23,
+                        len: 9,
+                    }),
+                ])
+                .into(),
+            },
+        ])];
+        assert_eq!(ast, expected);
+
+        // Every clause is optional, without a condition the loop runs forever
+        let ast = parse_code("for (;;) print i;").unwrap();
+        let expected = vec![Stmt::While {
+            condition: Literal {
+                value: True.into(),
+                index: 0,
+                len: 3,
+            },
+            body: Stmt::Print(variable(15)).into(),
+        }];
+        assert_eq!(ast, expected);
+
+        let err = parse_error("for (i = 0; i < 2) print i;");
+        assert_eq!(err.message, "Expected ';' after loop condition");
+        assert_eq!((err.index, err.len), (17, 1));
+    }
+
+    #[test]
+    fn unexpected_unary_operator() {
+        let token = |kind| Token {
+            kind,
+            index: 3,
+            len: 1,
+            line: 1,
+            column: 4,
+        };
+        assert_eq!(unary_operator(token(TokenKind::Bang)).unwrap(), LogicNegate);
+        let err = unary_operator(token(TokenKind::Plus)).unwrap_err();
+        assert_eq!(err.message, "Plus is not a valid unary operation");
+        assert_eq!((err.index, err.len), (3, 1));
+    }
+
+    #[test]
+    fn parse_logical_precedence() {
+        let variable = |value: &str, index| Variable {
+            value: String::from(value),
+            index,
+            len: 1,
+        };
+
+        // `and` binds tighter than `or`, and both looser than equality
+        let ast = parse_expr("a or b and c == d").unwrap();
+        let expected = Logical {
+            left: variable("a", 0).into(),
+            operator: Or,
+            right: Logical {
+                left: variable("b", 5).into(),
+                operator: And,
+                right: Binary {
+                    left: variable("c", 11).into(),
+                    operator: Equals,
+                    right: variable("d", 16).into(),
+                    index: 11,
+                    len: 6,
+                }
+                .into(),
+                index: 5,
+                len: 12,
+            }
+            .into(),
+            index: 0,
+            len: 17,
+        };
+        assert_eq!(ast, expected);
+
+        // Assignment is looser than `or`
+        let ast = parse_expr("a = b or c").unwrap();
+        assert!(matches!(
+            &ast,
+            Assign { value, .. } if matches!(**value, Logical { operator: Or, .. })
+        ));
+        // So `or` can't be assigned to
+        let err = parse_expr("a or b = c").unwrap_err();
+        assert_eq!((err.code(), err.span()), ("E005", (0, 6)));
+    }
+
+    #[test]
+    fn parse_function_declaration() {
+        let ast = parse_code("fun add(a, b) { return a + b; } fun f() { return; }").unwrap();
+        match &ast[..] {
+            [Stmt::Function {
+                name,
+                params,
+                attributes,
+                body,
+                index: 4,
+                len: 3,
+            }, Stmt::Function {
+                params: no_params,
+                body: returns_nil,
+                ..
+            }] => {
+                assert_eq!(name, "add");
+                assert_eq!(params, &["a", "b"]);
+                assert!(attributes.is_empty());
+                assert!(matches!(
+                    &body[..],
+                    [Stmt::Return {
+                        value: Some(Binary { .. }),
+                        index: 16,
+                        len: 6
+                    }]
+                ));
+                assert!(no_params.is_empty());
+                assert!(matches!(
+                    &returns_nil[..],
+                    [Stmt::Return { value: None, .. }]
+                ));
+            }
+            ast => panic!("Expected two functions but got {:?}", ast),
+        }
+
+        let err = parse_error("fun (a) {}");
+        assert_eq!(err.message, "Expected function name");
+        assert_eq!((err.index, err.len), (4, 1));
+        let err = parse_error("fun f(a, 1) {}");
+        assert_eq!(err.message, "Expected parameter name");
+        assert_eq!((err.index, err.len), (9, 1));
+        let err = parse_error("fun f(a) return a;");
+        assert_eq!(err.message, "Expected '{' before function body");
+        assert_eq!((err.index, err.len), (9, 6));
+        let err = parse_error("fun f() { return }");
+        assert_eq!(
+            err.message,
+            "Token \"RightBrace\" does not match a valid expression"
+        );
+    }
+
+    #[test]
+    fn parse_reports_every_error() {
+        let errors =
+            parse_code("var = 1;\nprint 2;\nprint 3 +;\nvar x = (1;\nprint x;").unwrap_err();
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|e| (e.message.as_str(), e.index))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("Expected variable name but found Assign", 4),
+                ("Token \"Semicolon\" does not match a valid expression", 27),
+                (
+                    "The token Semicolon was not expected, a ')' was expected",
+                    39
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_top_level_return() {
+        let err = parse_error("return 1;");
+        assert_eq!(err.message, "Can't return from top-level code");
+        assert_eq!((err.index, err.len), (0, 6));
+        let err = parse_code("while (true) { if (false) {} else return; }")
+            .unwrap_err()
+            .remove(0);
+        assert_eq!((err.index, err.len), (34, 6));
+        assert!(parse_code("fun f() { { while (true) return; } }").is_ok());
+    }
+
+    #[test]
+    fn parse_calls() {
+        let ast = parse_expr("f(1, a = 2)()").unwrap();
+        let expected = Call {
+            callee: Call {
+                callee: Variable {
+                    value: String::from("f"),
+                    index: 0,
+                    len: 1,
+                }
+                .into(),
+                arguments: Box::new([
+                    Literal {
+                        value: Number(1.0).into(),
+                        index: 2,
+                        len: 1,
+                    },
+                    Assign {
+                        key: String::from("a"),
+                        value: Literal {
+                            value: Number(2.0).into(),
+                            index: 9,
+                            len: 1,
+                        }
+                        .into(),
+                        index: 5,
+                        len: 5,
+                    },
+                ]),
+                index: 0,
+                len: 11,
+            }
+            .into(),
+            arguments: Box::new([]),
+            index: 0,
+            len: 13,
+        };
+        assert_eq!(ast, expected);
+
+        let err = parse_expr("f(1").unwrap_err();
+        assert_eq!(err.message, "Expected ')' after arguments");
+        assert_eq!((err.index, err.len), (3, 0));
+
+        let arguments = vec!["1"; 256].join(", ");
+        let err = parse_expr(&format!("f({})", arguments)).unwrap_err();
+        assert_eq!(err.message, "Can't pass more than 255 arguments");
+        assert_eq!(err.index, 2 + 255 * 3);
+    }
+
+    #[test]
+    fn parse_postfix_bang() {
+        let ast = parse_expr("!x!").unwrap();
+        let expected = Unary {
+            operator: LogicNegate,
+            right: Unary {
+                operator: AssertNotNil,
+                right: Variable {
+                    value: String::from("x"),
+                    index: 1,
+                    len: 1,
+                }
+                .into(),
+                index: 1,
+                len: 2,
+            }
+            .into(),
+            index: 0,
+            len: 3,
+        };
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn parse_too_deeply_nested() {
+        let code = format!("{}1{};", "(".repeat(10_000), ")".repeat(10_000));
+        let err = parse_error(&code);
+        assert_eq!(err.message, "Expression too deeply nested");
+        assert_eq!(err.index, 101);
+
+        let code = format!("{}true;", "!".repeat(100));
+        let config = Config {
+            max_depth: 100,
+            ..Config::default()
+        };
+        let mut tokens = tokenize(&code)
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .peekable();
+        assert!(parse(&mut tokens, &config).is_ok());
+
+        // Blocks count towards the same limit as the expressions inside them
+        let code = format!("{}(1);{}", "{".repeat(100), "}".repeat(100));
+        let mut tokens = tokenize(&code)
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .peekable();
+        let err = parse(&mut tokens, &config).unwrap_err().remove(0);
+        assert_eq!(
+            (err.message.as_str(), err.index),
+            ("Expression too deeply nested", 101)
+        );
+    }
+
+    #[test]
+    fn error_codes() {
+        for (program, code) in [
+            ("print 1", "E001"),
+            ("var 1;", "E002"),
+            ("1 +;", "E003"),
+            ("{ print 1;", "E004"),
+            ("(a) = 1;", "E005"),
+            ("return 1;", "E007"),
+            ("0x;", "E008"),
+            ("print \"C:\\quarter\";", "E010"),
+            ("print 1 # 2;", "E011"),
+        ] {
+            let err = parse_error(program);
+            assert_eq!(err.code(), code, "{}", program);
+            assert!(crate::diagnostics::explain(code).is_some());
+        }
+    }
+
+    #[test]
+    fn parse_dots() {
+        let diagnose = |code: &str| {
+            let err = parse_error(code);
+            (err.code(), err.render(code))
+        };
+
+        assert_eq!(
+            diagnose(".x;"),
+            ("E003", String::from("Error[E003]: Unexpected '.' at 1:1"))
+        );
+        assert_eq!(
+            diagnose("1 ."),
+            (
+                "E002",
+                String::from("Error[E002]: Expected property name after '.' at 1:4")
+            )
+        );
+        assert_eq!(
+            diagnose("1.;"),
+            (
+                "E002",
+                String::from("Error[E002]: Expected property name after '.' at 1:3")
+            )
+        );
+        // There are no get expressions yet, so property accesses are rejected as a whole
+        let err = parse_error("f().b;");
+        assert_eq!(
+            (err.message.as_str(), err.index, err.len),
+            ("Can't access property 'b', values have no properties", 3, 2)
+        );
+    }
+
+    #[test]
+    fn parse_lenient_semicolons() {
+        let code = "var a = 1\nprint a\n{ a = 2 }\nreturn_value()";
+        let parse_with = |config: &Config| {
+            let mut tokens = tokenize_with_eof(code)
+                .filter(|t| t.kind != TokenKind::Whitespace)
+                .peekable();
+            parse(&mut tokens, config)
+        };
+
+        let err = parse_with(&Config::default()).unwrap_err().remove(0);
+        assert_eq!(err.code(), "E001");
+
+        let config = Config {
+            lenient_semicolons: true,
+            ..Config::default()
+        };
+        let ast = parse_with(&config).unwrap();
+        assert_eq!(ast.len(), 4);
+        assert!(matches!(ast[2], Stmt::Block(ref stmts) if stmts.len() == 1));
+        // Semicolons are still taken when they are there
+        let mut tokens = tokenize_with_eof("print 1; print 2")
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .peekable();
+        assert_eq!(parse(&mut tokens, &config).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn parse_function_attributes() {
+        let ast = parse_code("@foo fun g(){} @a @b\nfun h() {}").unwrap();
+        match &ast[..] {
+            [Stmt::Function {
+                name: g,
+                attributes: foo,
+                index: 9,
+                ..
+            }, Stmt::Function {
+                name: h,
+                attributes: a_b,
+                ..
+            }] => {
+                assert_eq!(
+                    (g.as_str(), foo.as_slice()),
+                    ("g", &[String::from("foo")][..])
+                );
+                assert_eq!(h, "h");
+                assert_eq!(a_b, &["a", "b"]);
+            }
+            ast => panic!("Expected two functions but got {:?}", ast),
+        }
+
+        for (code, message, span) in [
+            ("@ fun f() {}", "Expected attribute name after '@'", (2, 3)),
+            (
+                "@foo var a;",
+                "Expected a function declaration after its attributes",
+                (5, 3),
+            ),
+            (
+                "@foo",
+                "Expected a function declaration after its attributes",
+                (4, 0),
+            ),
+            (
+                "{ @foo 1; }",
+                "Expected a function declaration after its attributes",
+                (7, 1),
+            ),
+        ] {
+            let err = parse_error(code);
+            assert_eq!(
+                (err.message.as_str(), err.span()),
+                (message, span),
+                "{}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn parse_interned_literals() {
+        let interning = Config {
+            intern_literals: true,
+            ..Config::default()
+        };
+        let shared = |code: &str, config: &Config| match &parse_expr_with(code, config).unwrap() {
+            Binary { left, right, .. } => match (&**left, &**right) {
+                (Literal { value: l, .. }, Literal { value: r, .. }) => Rc::ptr_eq(l, r),
+                e => panic!("Expected two literals but got {:?}", e),
+            },
+            e => panic!("Expected a binary expression but got {:?}", e),
+        };
+
+        assert!(shared("true == true", &interning));
+        assert!(!shared("true == true", &Config::default()));
+        for code in ["false == false", "nil == nil", "0 == 0", "255 == 255"] {
+            assert!(shared(code, &interning), "{}", code);
+        }
+        for code in ["256 == 256", "1.5 == 1.5", "\"a\" == \"a\""] {
+            assert!(!shared(code, &interning), "{}", code);
+        }
+    }
+
+    #[test]
+    fn parse_unexpected_characters() {
+        for (code, index) in [
+            ("1 @ 2;", 2),
+            ("print 1 # 2;", 8),
+            ("var a = $;", 8),
+            ("(1 ~ 2);", 3),
+        ] {
+            let err = parse_error(code);
+            assert_eq!(err.code(), "E011", "{}", code);
+            assert_eq!(err.span(), (index, 1), "{}", code);
+            let c = &code[index..index + 1];
+            assert_eq!(err.message, format!("Unexpected character '{}'", c));
+        }
+    }
 }
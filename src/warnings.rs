@@ -0,0 +1,323 @@
+use std::fmt::Display;
+
+use crate::{
+    ast::{Expr, Stmt},
+    diagnostics,
+};
+
+/// Something suspicious about a program that doesn't stop it from running
+#[derive(Debug, PartialEq)]
+pub struct LoxWarning {
+    code: &'static str,
+    message: String,
+    index: usize,
+    len: usize,
+}
+
+impl LoxWarning {
+    /// Like the `Display` form but locating the warning by line and column within `code`
+    pub fn render(&self, code: &str) -> String {
+        self.render_at(code, 1)
+    }
+
+    /// Like `render` for `code` starting at line `first_line` of a larger source
+    pub fn render_at(&self, code: &str, first_line: usize) -> String {
+        diagnostics::locate(
+            "Warning",
+            self.code,
+            &self.message,
+            self.index,
+            code,
+            first_line,
+        )
+    }
+}
+
+impl Display for LoxWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Warning[{}]: {} at {} until {}",
+            self.code, self.message, self.index, self.len
+        )
+    }
+}
+
+/// Runs every warning pass over a program
+pub fn check(stmts: &[Stmt]) -> Vec<LoxWarning> {
+    let mut checker = Checker::default();
+    checker.stmts(stmts);
+    checker.warnings
+}
+
+/// A local variable and whether it was read
+struct Local<'a> {
+    name: &'a str,
+    index: usize,
+    len: usize,
+    used: bool,
+}
+
+#[derive(Default)]
+struct Checker<'a> {
+    // Block scopes, innermost last. Globals aren't tracked since later files may use them.
+    scopes: Vec<Vec<Local<'a>>>,
+    warnings: Vec<LoxWarning>,
+}
+
+impl<'a> Checker<'a> {
+    fn stmts(&mut self, stmts: &'a [Stmt]) {
+        for (i, stmt) in stmts.iter().enumerate() {
+            // Only the first statement after a `return` is reported, the rest go with it
+            let after_return = i > 0 && matches!(stmts[i - 1], Stmt::Return { .. });
+            if let (true, Some((index, len))) = (after_return, span(stmt)) {
+                self.warnings.push(LoxWarning {
+                    code: "W003",
+                    message: String::from("This code is never reached"),
+                    index,
+                    len,
+                });
+            }
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt) {
+        match stmt {
+            Stmt::Expression(e) => {
+                if !has_side_effects(e) {
+                    self.warnings.push(LoxWarning {
+                        code: "W002",
+                        message: String::from("The value of this expression is never used"),
+                        index: e.index(),
+                        len: e.len(),
+                    });
+                }
+                self.expr(e);
+            }
+            Stmt::Print(e) => self.expr(e),
+            Stmt::Variable {
+                name,
+                initializer,
+                index,
+                len,
+            } => {
+                // The initializer runs before the name is declared
+                if let Some(e) = initializer {
+                    self.expr(e);
+                }
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.push(Local {
+                        name,
+                        index: *index,
+                        len: *len,
+                        used: false,
+                    });
+                }
+            }
+            Stmt::Block(stmts) => {
+                self.scopes.push(Vec::new());
+                self.stmts(stmts);
+                self.end_scope();
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(condition);
+                self.stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            Stmt::While { condition, body } => {
+                self.expr(condition);
+                self.stmt(body);
+            }
+            Stmt::Function {
+                name,
+                params,
+                body,
+                index,
+                len,
+                ..
+            } => {
+                // Declared before the body so recursive calls count as using it
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.push(Local {
+                        name,
+                        index: *index,
+                        len: *len,
+                        used: false,
+                    });
+                }
+                // Parameters are part of the signature, so unused ones aren't reported
+                self.scopes.push(
+                    params
+                        .iter()
+                        .map(|name| Local {
+                            name,
+                            index: *index,
+                            len: *len,
+                            used: true,
+                        })
+                        .collect(),
+                );
+                self.stmts(body);
+                self.end_scope();
+            }
+            Stmt::Return { value, .. } => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+        }
+    }
+
+    /// Pops the innermost scope, reporting the locals that were never read
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap_or_default();
+        self.warnings
+            .extend(scope.into_iter().filter(|l| !l.used).map(|l| LoxWarning {
+                code: "W001",
+                message: format!("The variable '{}' is never used", l.name),
+                index: l.index,
+                len: l.len,
+            }));
+    }
+
+    fn expr(&mut self, expr: &'a Expr) {
+        match expr {
+            Expr::Variable { value, .. } => {
+                let local = self
+                    .scopes
+                    .iter_mut()
+                    .rev()
+                    .find_map(|scope| scope.iter_mut().rev().find(|l| l.name == value));
+                if let Some(local) = local {
+                    local.used = true;
+                }
+            }
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::Grouping { expr, .. } => self.expr(expr),
+            Expr::Unary { right, .. } => self.expr(right),
+            Expr::Ternary {
+                condition,
+                left,
+                right,
+                ..
+            } => {
+                self.expr(condition);
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.expr(callee);
+                for argument in arguments {
+                    self.expr(argument);
+                }
+            }
+            // Assigning a variable doesn't count as using it
+            Expr::Assign { value, .. } => self.expr(value),
+            Expr::Literal { .. } => {}
+        }
+    }
+}
+
+/// Where a statement is, blocks being where their first statement is. `None` for empty blocks.
+fn span(stmt: &Stmt) -> Option<(usize, usize)> {
+    match stmt {
+        Stmt::Block(stmts) => stmts.first().and_then(span),
+        stmt => stmt.span(),
+    }
+}
+
+fn has_side_effects(expr: &Expr) -> bool {
+    match expr {
+        Expr::Assign { .. } | Expr::Call { .. } => true,
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            has_side_effects(left) || has_side_effects(right)
+        }
+        Expr::Grouping { expr, .. } => has_side_effects(expr),
+        Expr::Unary { right, .. } => has_side_effects(right),
+        Expr::Ternary {
+            condition,
+            left,
+            right,
+            ..
+        } => has_side_effects(condition) || has_side_effects(left) || has_side_effects(right),
+        Expr::Literal { .. } | Expr::Variable { .. } => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check;
+    use crate::config::Config;
+    use crate::lexer::{tokenize, TokenKind};
+    use crate::parser::parse;
+
+    fn warnings(code: &str) -> Vec<String> {
+        let mut tokens = tokenize(code)
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .peekable();
+        let ast = parse(&mut tokens, &Config::default()).unwrap();
+        check(&ast).iter().map(|w| w.render(code)).collect()
+    }
+
+    #[test]
+    fn unused_locals() {
+        assert_eq!(
+            warnings("var global; { var a = 1; var b = a; { var a; } b = 2; }"),
+            vec![
+                "Warning[W001]: The variable 'a' is never used at 1:43",
+                "Warning[W001]: The variable 'b' is never used at 1:30",
+            ]
+        );
+        assert!(warnings("{ var a = 1; { print a; } }").is_empty());
+        // The initializer reads the outer `a`
+        assert!(warnings("{ var a = 1; { var a = a; print a; } }").is_empty());
+    }
+
+    #[test]
+    fn function_locals() {
+        assert_eq!(
+            warnings("fun f(a, b) { var c; return a; }"),
+            vec!["Warning[W001]: The variable 'c' is never used at 1:19"]
+        );
+        // Functions calling themselves count as used
+        assert_eq!(
+            warnings("{ fun g() {} fun h() { h(); } }"),
+            vec!["Warning[W001]: The variable 'g' is never used at 1:7"]
+        );
+    }
+
+    #[test]
+    fn unused_expression_values() {
+        assert_eq!(
+            warnings("var a; 1 + 2;\na = 1; (a = 2) + 1;"),
+            vec!["Warning[W002]: The value of this expression is never used at 1:8"]
+        );
+    }
+
+    #[test]
+    fn unreachable_code() {
+        assert_eq!(
+            warnings("fun f(a) { return a; print 1; print 2; }\nfun g() { return; { var b; } }"),
+            vec![
+                "Warning[W003]: This code is never reached at 1:28",
+                "Warning[W003]: This code is never reached at 2:25",
+                "Warning[W001]: The variable 'b' is never used at 2:25",
+            ]
+        );
+        // Returns in a nested block only end that block
+        assert!(warnings("fun f(a) { if (a) { return 1; } return 2; }").is_empty());
+        assert!(warnings("fun f(a) { while (a) return 1; print a; }").is_empty());
+    }
+}
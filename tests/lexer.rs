@@ -0,0 +1,42 @@
+use lox::lexer::{self, KeywordKind, LiteralKind, TokenKind};
+
+#[test]
+fn tokenize_from_another_crate() {
+    let code = "var é = 1.5;\nprint é;";
+    let tokens: Vec<_> = lexer::tokenize(code)
+        .filter(|t| t.kind != TokenKind::Whitespace)
+        .collect();
+
+    let kinds: Vec<_> = tokens.iter().map(|t| t.kind.clone()).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            TokenKind::Keyword(KeywordKind::Var),
+            TokenKind::Identifier(String::from("é")),
+            TokenKind::Assign,
+            TokenKind::Literal(LiteralKind::Number {
+                value: 1.5,
+                is_int: false
+            }),
+            TokenKind::Semicolon,
+            TokenKind::Keyword(KeywordKind::Print),
+            TokenKind::Identifier(String::from("é")),
+            TokenKind::Semicolon,
+        ]
+    );
+
+    // Spans are in bytes, positions in characters
+    let print = &tokens[5];
+    assert_eq!(&code[print.index..print.index + print.len], "print");
+    assert_eq!((print.line, print.column), (2, 1));
+    let name = &tokens[6];
+    assert_eq!((name.len, name.column), (2, 7));
+}
+
+#[test]
+fn character_predicates() {
+    assert!(lexer::is_digit('7') && !lexer::is_digit('x'));
+    assert!(lexer::is_whitespace('\t') && !lexer::is_whitespace('_'));
+    assert!(lexer::is_ident_start('_') && !lexer::is_ident_start('1'));
+    assert!(lexer::is_ident_continue('1'));
+}
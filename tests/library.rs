@@ -0,0 +1,23 @@
+use std::{cell::RefCell, rc::Rc};
+
+use lox::interpreter::{Environment, LoxResult};
+
+#[test]
+fn programs_share_the_given_environment() {
+    let env = Rc::new(RefCell::new(Environment::new()));
+    lox::interpret("var a = 1;", env.clone()).unwrap();
+    lox::interpret(
+        "fun double(x) { return x * 2; } a = double(a + 1);",
+        env.clone(),
+    )
+    .unwrap();
+    assert_eq!(env.borrow().get("a"), Some(Some(LoxResult::Number(4.0))));
+
+    let err = lox::interpret("var b = 1;\nb + nil;", env.clone()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error[E101]: Cant operate on Number and Nil at 2:1"
+    );
+    // Declarations before the error stay around
+    assert_eq!(env.borrow().get("b"), Some(Some(LoxResult::Number(1.0))));
+}
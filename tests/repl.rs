@@ -0,0 +1,74 @@
+// Ctrl-C is sent as SIGINT with `kill`
+#![cfg(unix)]
+
+use std::{
+    io::{Read, Write},
+    process::{Child, ChildStdout, Command, Stdio},
+    thread::sleep,
+    time::Duration,
+};
+
+fn start_repl() -> Child {
+    Command::new(env!("CARGO_BIN_EXE_lox"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap()
+}
+
+fn interrupt(repl: &Child) {
+    let status = Command::new("kill")
+        .args(["-INT", &repl.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// Reads the output of the REPL until it is `expected`, which it has to start with
+fn expect_output(stdout: &mut ChildStdout, expected: &str) {
+    let mut output = Vec::new();
+    while output.len() < expected.len() {
+        let mut byte = [0];
+        stdout.read_exact(&mut byte).unwrap();
+        output.push(byte[0]);
+    }
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+}
+
+#[test]
+fn interrupt_cancels_the_line_being_read() {
+    let mut repl = start_repl();
+    let mut stdout = repl.stdout.take().unwrap();
+    expect_output(&mut stdout, "Running repl\n> ");
+
+    interrupt(&repl);
+    expect_output(&mut stdout, "\n> ");
+    let mut stdin = repl.stdin.take().unwrap();
+    stdin.write_all(b"print 1;\n").unwrap();
+    drop(stdin);
+
+    expect_output(&mut stdout, "1\n> ");
+    assert!(repl.wait().unwrap().success());
+}
+
+#[test]
+fn interrupt_stops_the_running_loop() {
+    let mut repl = start_repl();
+    let mut stdout = repl.stdout.take().unwrap();
+    expect_output(&mut stdout, "Running repl\n> ");
+
+    let mut stdin = repl.stdin.take().unwrap();
+    stdin.write_all(b"while (true) {}\n").unwrap();
+    // Long enough for the loop to be running when interrupted
+    sleep(Duration::from_millis(200));
+    interrupt(&repl);
+    stdin.write_all(b"print 2;\n").unwrap();
+    drop(stdin);
+
+    expect_output(&mut stdout, "> 2\n> ");
+    let output = repl.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Error[E109]: Interrupted"), "{}", stderr);
+}
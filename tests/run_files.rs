@@ -0,0 +1,387 @@
+use std::{path::PathBuf, process::Command};
+
+use lox::lexer::Chunks;
+
+fn write_script(name: &str, code: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("lox-{}-{}", std::process::id(), name));
+    std::fs::write(&path, code).unwrap();
+    path
+}
+
+#[test]
+fn later_files_see_earlier_declarations() {
+    let first = write_script("first.lox", "fun greet(n) { return \"hello \" + n; }\n");
+    let second = write_script("second.lox", "print greet(\"world\");\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg(&first)
+        .arg(&second)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "hello world\n");
+}
+
+#[test]
+fn stops_at_the_first_failing_file() {
+    let first = write_script("failing.lox", "print undeclared;\n");
+    let second = write_script("never_run.lox", "print 1;\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg(&first)
+        .arg(&second)
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+}
+
+fn nested(depth: usize) -> String {
+    format!("print {}1{};\n", "(".repeat(depth), ")".repeat(depth))
+}
+
+#[test]
+fn runtime_errors_quote_the_source() {
+    let script = write_script(
+        "runtime_error.lox",
+        "var name = \"lox\";\nprint name;\nprint -name;\nprint \"never\";\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg(&script)
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "lox\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Error[E101]: "), "{}", stderr);
+    assert!(
+        stderr.ends_with(" at 3:7\n  |\n3 | print -name;\n  |       ^^^^^\n"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn deep_nesting_runs_on_the_large_stack() {
+    let script = write_script("deep.lox", &nested(1500));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg(&script)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+}
+
+#[test]
+fn long_operator_chains_run() {
+    let script = write_script(
+        "long_chain.lox",
+        &format!("print {};\n", vec!["1"; 30_000].join(" + ")),
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg(&script)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "30000\n");
+}
+
+#[test]
+fn nesting_past_the_limit_is_reported() {
+    let script = write_script("too_deep.lox", &nested(100_000));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg(&script)
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Expression too deeply nested"));
+}
+
+#[test]
+fn runaway_recursion_is_reported() {
+    let script = write_script("recursion.lox", "fun f(n) { return f(n + 1); }\nf(0);\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg(&script)
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(70));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("Error[E108]: Too many nested calls"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("1 | fun f(n) { return f(n + 1); }"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn denied_warnings_stop_the_program() {
+    let script = write_script("warnings.lox", "{ var unused = 1; }\nprint 1;\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Warning[W001]: The variable 'unused' is never used at 1:7\n"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("--deny-warnings")
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+}
+
+#[test]
+fn comma_operator_is_opt_in() {
+    let script = write_script("comma.lox", "print (1, 2);\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Unexpected ',' outside of an argument list"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("--comma-operator")
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "2
+"
+    );
+}
+
+#[test]
+fn coercion_is_opt_in() {
+    let script = write_script("coerce.lox", "print \"3\" + 4;\nprint \"6\" * 2;\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "34\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("--coerce")
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "7\n12\n");
+}
+
+#[test]
+fn coverage_lists_statements_never_run() {
+    let script = write_script(
+        "coverage.lox",
+        "var a = 1;\nif (a == 1) print \"one\";\nelse print \"other\";\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("--coverage")
+        .arg(&script)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "one\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "Coverage of {}: 3 of 4 statements ran\nNever ran at 3:12\n  |\n3 | else print \"other\";\n  |            ^^^^^^^\n",
+            script.display()
+        )
+    );
+}
+
+/// Script of `statements` lines adding up to well over the size large files are streamed from
+fn large_script(statements: usize) -> String {
+    let mut code = String::from("var total = 0;\nfun add(n, note) {\n  total = total + n;\n}\n");
+    for i in 0..statements {
+        code.push_str(&format!("add({}, \"{}\");\n", i, "padding ".repeat(8)));
+    }
+    code
+}
+
+#[test]
+fn large_files_are_streamed() {
+    let mut code = large_script(20_000);
+    assert!(code.len() > 1024 * 1024);
+    code.push_str("if (total > 0)\n  print total;\nelse\n  print \"none\";\n");
+    let script = write_script("large.lox", &code);
+    // The declarations, one chunk per call and the `if` with its `else`
+    assert_eq!(Chunks::new(code.as_bytes()).count(), 20_003);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg(&script)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "199990000\n");
+}
+
+#[test]
+fn large_files_report_errors_at_their_line() {
+    let mut code = large_script(20_000);
+    code.push_str("print total;\nprint total - nil;\nprint \"never\";\n");
+    let script = write_script("large_failing.lox", &code);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg(&script)
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "199990000\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        concat!(
+            "Error[E101]: Cant operate on Number and Nil at 20006:7\n",
+            "      |\n",
+            "20006 | print total - nil;\n",
+            "      |       ^^^^^^^^^^^\n"
+        )
+    );
+
+    // Syntax errors stop the file before any of it runs
+    code.push_str("print 1 +;\n");
+    let script = write_script("large_invalid.lox", &code);
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        concat!(
+            "Error[E003]: Token \"Semicolon\" does not match a valid expression at 20008:10\n",
+            "      |\n",
+            "20008 | print 1 +;\n",
+            "      |          ^\n"
+        )
+    );
+}
+
+#[test]
+fn streaming_threshold() {
+    let code = "var a = 1;\nprint a;\n{\n  print a + 1;\n}\nprint a - nil;\n";
+    assert_eq!(Chunks::new(code.as_bytes()).count(), 4);
+    let script = write_script("streaming_threshold.lox", code);
+
+    // Streamed with a threshold of 0 and read whole with one above its size, the same either way
+    for threshold in ["0", "1000"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+            .args(["--streaming-threshold", threshold])
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(70), "{}", threshold);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n2\n");
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            concat!(
+                "Error[E101]: Cant operate on Number and Nil at 6:7\n",
+                "  |\n",
+                "6 | print a - nil;\n",
+                "  |       ^^^^^^^\n"
+            )
+        );
+    }
+}
+
+#[test]
+fn streaming_reports_the_same_warnings() {
+    let code = concat!(
+        "var global = 1;\n",
+        "{\n  var a = 1;\n  var b;\n  a + 1;\n}\n",
+        "fun f() {\n  return 1;\n  print \"never\";\n}\n",
+        "1 + 2;\n",
+        "print f();\n",
+    );
+    assert_eq!(Chunks::new(code.as_bytes()).count(), 5);
+    let script = write_script("streaming_warnings.lox", code);
+
+    for options in [&[][..], &["--deny-warnings"]] {
+        let run = |threshold| {
+            Command::new(env!("CARGO_BIN_EXE_lox"))
+                .args(options)
+                .args(["--streaming-threshold", threshold])
+                .arg(&script)
+                .output()
+                .unwrap()
+        };
+        let (streamed, whole) = (run("0"), run("1000"));
+        assert_eq!(streamed.status.code(), whole.status.code(), "{:?}", options);
+        assert_eq!(streamed.stdout, whole.stdout, "{:?}", options);
+        assert_eq!(
+            String::from_utf8(streamed.stderr).unwrap(),
+            String::from_utf8(whole.stderr).unwrap(),
+            "{:?}",
+            options
+        );
+        assert_eq!(
+            whole.status.code(),
+            Some(if options.is_empty() { 0 } else { 65 })
+        );
+    }
+}
+
+#[test]
+fn unreadable_files_are_reported() {
+    let missing = std::env::temp_dir().join("lox-missing-file.lox");
+    let directory = std::env::temp_dir();
+
+    for (path, options) in [
+        (&missing, ["--streaming-threshold", "0"].as_slice()),
+        (&missing, &["--streaming-threshold", "1000"]),
+        (&directory, &["--streaming-threshold", "0"]),
+        (&missing, &["--emit-tokens-json"]),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+            .args(options)
+            .arg(path)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(74));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.starts_with(&format!("Error: can't read {}: ", path.display())),
+            "{}",
+            stderr
+        );
+    }
+}